@@ -0,0 +1,64 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::config::Config;
+use crate::s3::S3Client;
+
+/// `check-config` subcommand: prints every problem found, returns false if there are any
+pub async fn check_config(path: &str, probe: bool) -> bool {
+    let mut config = match Config::new(path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("ERROR {e}");
+            return false;
+        }
+    };
+    let mut problems = config.lint();
+
+    if let (true, Some(cert), Some(key)) = config.get_ssl() {
+        if let Err(e) = RustlsConfig::from_pem_file(cert, key).await {
+            problems.push(format!("SSL certificate {cert} / key {key}: {e}"));
+        }
+    }
+
+    if probe {
+        if config.is_sso() {
+            if let Err(e) = config.init_sso().await {
+                problems.push(format!("SSO well-known configuration: {e}"));
+            }
+        }
+        for bucket in &config.s3.buckets {
+            // no background tasks: a check must not crawl, purge trash or write index files
+            let result = match S3Client::new_detached(&config.s3, bucket.clone()).await {
+                Ok(client) => client.ping().await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(_) => println!("OK bucket {} is reachable", bucket.alias),
+                Err(e) => problems.push(format!("bucket {} is not reachable: {e}", bucket.alias)),
+            }
+        }
+    }
+
+    for problem in &problems {
+        println!("ERROR {problem}");
+    }
+    if problems.is_empty() {
+        println!("OK {path}");
+    }
+    problems.is_empty()
+}
@@ -0,0 +1,213 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::fmt::{Display, Formatter};
+
+use anyhow::{anyhow, bail};
+use axum::http::HeaderMap;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use s3::Tag;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Object tags the checksums are stored in
+pub const SHA256_TAG: &str = "s3clix-sha256";
+pub const CRC32C_TAG: &str = "s3clix-crc32c";
+
+pub const SHA256_HEADER: &str = "x-checksum-sha256";
+pub const CRC32C_HEADER: &str = "x-checksum-crc32c";
+
+/// Base64 digests, as S3 itself and RFC 3230 `Digest` header represent them
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Checksums {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crc32c: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ChecksumMismatch(pub String);
+
+impl Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Checksum mismatch: {}", self.0)
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// Full object digests computed while the data passes through
+pub struct Hasher {
+    sha256: Sha256,
+    crc32c: u32,
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self {
+            sha256: Sha256::new(),
+            crc32c: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.crc32c = crc32c::crc32c_append(self.crc32c, data);
+    }
+
+    pub fn crc32c(&self) -> u32 {
+        self.crc32c
+    }
+
+    pub fn finish(self) -> Checksums {
+        Checksums {
+            sha256: Some(STANDARD.encode(self.sha256.finalize())),
+            crc32c: Some(encode_crc32c(self.crc32c)),
+        }
+    }
+}
+
+pub fn encode_crc32c(crc: u32) -> String {
+    STANDARD.encode(crc.to_be_bytes())
+}
+
+/// Accepts base64 or hex of `len` bytes
+fn normalize(value: &str, len: usize) -> anyhow::Result<String> {
+    let value = value.trim();
+    let bytes = match value.len() == len * 2 && value.chars().all(|x| x.is_ascii_hexdigit()) {
+        true => hex::decode(value)?,
+        false => STANDARD
+            .decode(value)
+            .map_err(|_| anyhow!("{value} is neither hex nor base64 digest"))?,
+    };
+    if bytes.len() != len {
+        bail!("{value} is not a {len} bytes digest");
+    }
+    Ok(STANDARD.encode(bytes))
+}
+
+impl Checksums {
+    /// Digests the client expects, from `x-checksum-sha256`, `x-checksum-crc32c`
+    /// or `Digest: sha-256=...,crc32c=...`
+    pub fn expected(headers: &HeaderMap) -> anyhow::Result<Checksums> {
+        let header = |name: &str| headers.get(name).and_then(|x| x.to_str().ok());
+        let mut sha256 = header(SHA256_HEADER)
+            .map(|x| normalize(x, 32))
+            .transpose()?;
+        let mut crc32c = header(CRC32C_HEADER).map(|x| normalize(x, 4)).transpose()?;
+        for digest in header("digest").unwrap_or_default().split(',') {
+            let Some((algorithm, value)) = digest.split_once('=') else {
+                continue;
+            };
+            match algorithm.trim().to_lowercase().as_str() {
+                "sha-256" => sha256 = Some(normalize(value, 32)?),
+                "crc32c" => crc32c = Some(normalize(value, 4)?),
+                _ => {}
+            }
+        }
+        Ok(Checksums { sha256, crc32c })
+    }
+
+    /// Every expected digest must be present and equal
+    pub fn verify(&self, expected: &Checksums) -> Result<(), ChecksumMismatch> {
+        for (name, actual, expected) in [
+            ("SHA-256", &self.sha256, &expected.sha256),
+            ("CRC32C", &self.crc32c, &expected.crc32c),
+        ] {
+            match (actual, expected) {
+                (_, None) => {}
+                (None, Some(_)) => {
+                    return Err(ChecksumMismatch(format!("{name} is not available")))
+                }
+                (Some(actual), Some(expected)) if actual != expected => {
+                    return Err(ChecksumMismatch(format!(
+                        "{name} is {actual}, expected {expected}"
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_tags(tags: &[Tag]) -> Checksums {
+        let tag = |name: &str| tags.iter().find(|x| x.key() == name).map(|x| x.value());
+        Checksums {
+            sha256: tag(SHA256_TAG),
+            crc32c: tag(CRC32C_TAG),
+        }
+    }
+
+    pub fn tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags = Vec::new();
+        if let Some(sha256) = &self.sha256 {
+            tags.push((SHA256_TAG, sha256.clone()));
+        }
+        if let Some(crc32c) = &self.crc32c {
+            tags.push((CRC32C_TAG, crc32c.clone()));
+        }
+        tags
+    }
+
+    /// RFC 3230 `Digest` header value
+    pub fn digest_header(&self) -> Option<String> {
+        let mut digests = Vec::new();
+        if let Some(sha256) = &self.sha256 {
+            digests.push(format!("sha-256={sha256}"));
+        }
+        if let Some(crc32c) = &self.crc32c {
+            digests.push(format!("crc32c={crc32c}"));
+        }
+        match digests.is_empty() {
+            true => None,
+            false => Some(digests.join(",")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::HeaderMap;
+
+    use super::{Checksums, Hasher};
+
+    #[test]
+    fn test_expected() {
+        let mut hasher = Hasher::new();
+        hasher.update(b"hello ");
+        hasher.update(b"world");
+        let actual = hasher.finish();
+        assert_eq!(actual.crc32c.as_deref(), Some("yZRlqg=="));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-checksum-sha256",
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+                .parse()
+                .unwrap(),
+        );
+        headers.insert("digest", "crc32c=yZRlqg==".parse().unwrap());
+        let expected = Checksums::expected(&headers).unwrap();
+        assert_eq!(expected, actual);
+        assert!(actual.verify(&expected).is_ok());
+
+        headers.insert("x-checksum-crc32c", "00000000".parse().unwrap());
+        headers.remove("digest");
+        let expected = Checksums::expected(&headers).unwrap();
+        assert!(actual.verify(&expected).is_err());
+    }
+}
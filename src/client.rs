@@ -0,0 +1,559 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use clap::Subcommand;
+use futures_util::{StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use percent_encoding::percent_decode_str;
+use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, COOKIE};
+use reqwest::{Method, RequestBuilder, Response, Url};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+use crate::jobs::{JobReport, JobStatus};
+use crate::s3::{DeletePreview, FileList, Presigned};
+use crate::search::{SearchMode, SearchQuery, TRUNCATED_HEADER};
+use crate::share::ShareInfo;
+
+/// How often the progress of a server job is asked for
+const JOB_POLL: Duration = Duration::from_secs(1);
+
+/// Client for the /api of a running s3clix
+#[derive(clap::Args)]
+pub struct ClientArgs {
+    /// s3clix server URL
+    #[arg(short, long, env = "S3CLIX_URL")]
+    url: String,
+    /// API token from `api_tokens` of the server configuration
+    #[arg(long, env = "S3CLIX_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Bucket alias, the first bucket if not set
+    #[arg(short, long, env = "S3CLIX_BUCKET")]
+    bucket: Option<String>,
+    #[command(subcommand)]
+    command: ClientCommand,
+}
+
+#[derive(Subcommand)]
+enum ClientCommand {
+    /// List folder
+    Ls {
+        #[arg(default_value = "")]
+        path: String,
+    },
+    /// Download file, or folder with -r
+    Get {
+        remote: String,
+        /// Local file or folder, current folder if not set
+        local: Option<PathBuf>,
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Upload file, or folder with -r
+    Put {
+        local: PathBuf,
+        /// Remote path, ending with / to keep the local name
+        remote: String,
+        #[arg(short, long)]
+        recursive: bool,
+        /// What to do with existing files: Reject, Overwrite, Rename or Timestamp.
+        /// The bucket `overwrite_policy` if not set
+        #[arg(long)]
+        overwrite: Option<String>,
+    },
+    /// Delete file, or folder with -r
+    Rm {
+        path: String,
+        #[arg(short, long)]
+        recursive: bool,
+        /// With -r, show what would be deleted
+        #[arg(long, requires = "recursive")]
+        dry_run: bool,
+    },
+    /// Create folder
+    Mkdir { path: String },
+    /// Search files by name
+    Search {
+        #[command(flatten)]
+        query: Box<SearchArgs>,
+    },
+    /// Print presigned S3 URL of file, for download or with --put for upload
+    Presign {
+        path: String,
+        /// Validity, seconds. The bucket maximum if not set
+        #[arg(long)]
+        expires: Option<u64>,
+        #[arg(long)]
+        put: bool,
+    },
+    /// Create public download link to file
+    Share {
+        path: String,
+        /// Validity, seconds. A day or the server maximum if it's shorter by default
+        #[arg(long)]
+        expires_in: Option<u64>,
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        max_downloads: Option<u64>,
+    },
+}
+
+#[derive(clap::Args)]
+struct SearchArgs {
+    /// Matches everything if empty
+    #[arg(default_value = "")]
+    pattern: String,
+    #[arg(long, value_enum, default_value_t)]
+    mode: Mode,
+    /// Search under this folder only
+    #[arg(long)]
+    prefix: Option<String>,
+    #[arg(long)]
+    min_size: Option<u64>,
+    #[arg(long)]
+    max_size: Option<u64>,
+    /// RFC 3339 time or a date as 2025-01-31
+    #[arg(long)]
+    modified_after: Option<String>,
+    /// RFC 3339 time or a date as 2025-01-31
+    #[arg(long)]
+    modified_before: Option<String>,
+    /// Return folders matching the pattern too
+    #[arg(long)]
+    folders: bool,
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+enum Mode {
+    /// Part of the path, case-insensitive
+    #[default]
+    Substring,
+    /// `*`, `?`, `**` and `[...]`, case-insensitive. Matches the name, or the whole path if there is `/`
+    Glob,
+    /// Regular expression anywhere in the path, case-insensitive unless it starts with `(?-i)`
+    Regex,
+}
+
+impl From<SearchArgs> for SearchQuery {
+    fn from(args: SearchArgs) -> Self {
+        SearchQuery {
+            pattern: args.pattern,
+            mode: match args.mode {
+                Mode::Substring => SearchMode::Substring,
+                Mode::Glob => SearchMode::Glob,
+                Mode::Regex => SearchMode::Regex,
+            },
+            prefix: args.prefix,
+            min_size: args.min_size,
+            max_size: args.max_size,
+            modified_after: args.modified_after,
+            modified_before: args.modified_before,
+            folders: args.folders,
+            limit: args.limit,
+        }
+    }
+}
+
+struct Client {
+    cli: reqwest::Client,
+    url: Url,
+    token: Option<String>,
+    bucket: Option<String>,
+}
+
+pub async fn run(args: ClientArgs) -> anyhow::Result<()> {
+    let client = Client {
+        // SSO would redirect to the login page, which makes no sense here
+        cli: reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?,
+        url: Url::parse(&args.url)?,
+        token: args.token,
+        bucket: args.bucket,
+    };
+    match args.command {
+        ClientCommand::Ls { path } => {
+            for file in client.list(&folder(&path)).await? {
+                match file.folder {
+                    true => println!("{:>12}  {}", "DIR", file.path),
+                    false => println!("{:>12}  {}", file.size, file.path),
+                }
+            }
+        }
+        ClientCommand::Get {
+            remote,
+            local,
+            recursive,
+        } => {
+            let local = local.unwrap_or_else(|| PathBuf::from("."));
+            match recursive {
+                true => client.get_folder(&folder(&remote), &local).await?,
+                false => {
+                    let remote = remote.trim_start_matches('/');
+                    let local = match local.is_dir() {
+                        true => local.join(file_name(remote)),
+                        false => local,
+                    };
+                    client.get(remote, &local).await?
+                }
+            }
+        }
+        ClientCommand::Put {
+            local,
+            remote,
+            recursive,
+            overwrite,
+        } => {
+            let remote = remote.trim_start_matches('/');
+            let name = local
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let remote = match remote.is_empty() || remote.ends_with('/') {
+                true => format!("{remote}{name}"),
+                false => remote.to_owned(),
+            };
+            let overwrite = overwrite.as_deref();
+            match recursive {
+                true => {
+                    client
+                        .put_folder(&local, &folder(&remote), overwrite)
+                        .await?
+                }
+                false => client.put(&local, &remote, overwrite).await?,
+            }
+        }
+        ClientCommand::Rm {
+            path,
+            recursive,
+            dry_run,
+        } => match recursive {
+            true if dry_run => {
+                let mut url = client.url("deleteFolder", &folder(&path))?;
+                url.query_pairs_mut().append_pair("dry_run", "true");
+                let preview: DeletePreview =
+                    check(client.request(Method::DELETE, url).send().await?)
+                        .await?
+                        .json()
+                        .await?;
+                println!("{} files, {} bytes", preview.count, preview.size);
+                preview.sample.iter().for_each(|x| println!("{x}"));
+                if preview.sample.len() < preview.count {
+                    println!("...");
+                }
+            }
+            true => client.delete_folder(&folder(&path)).await?,
+            false => {
+                client
+                    .send(Method::DELETE, "delete", path.trim_start_matches('/'))
+                    .await?;
+            }
+        },
+        ClientCommand::Mkdir { path } => {
+            client
+                .send(Method::POST, "mkdir", folder(&path).trim_end_matches('/'))
+                .await?;
+        }
+        ClientCommand::Search { query } => {
+            let query = SearchQuery::from(*query);
+            let url = client.url("search", "")?;
+            let response = check(
+                client
+                    .request(Method::GET, url)
+                    .query(&query)
+                    .send()
+                    .await?,
+            )
+            .await?;
+            let truncated = response.headers().contains_key(TRUNCATED_HEADER);
+            let files: Vec<FileList> = response.json().await?;
+            for file in &files {
+                println!("{:>12}  {}", file.size, file.path);
+            }
+            if truncated {
+                eprintln!("Only the first {} results are shown", files.len());
+            }
+        }
+        ClientCommand::Presign { path, expires, put } => {
+            let mut url = client.url("presign", path.trim_start_matches('/'))?;
+            if let Some(expires) = expires {
+                url.query_pairs_mut()
+                    .append_pair("expires", &expires.to_string());
+            }
+            if put {
+                url.query_pairs_mut().append_pair("method", "put");
+            }
+            let presigned: Presigned = check(client.request(Method::GET, url).send().await?)
+                .await?
+                .json()
+                .await?;
+            if presigned.path != path.trim_start_matches('/') {
+                eprintln!("File exists, the URL uploads to {}", presigned.path);
+            }
+            println!("{}", presigned.url);
+        }
+        ClientCommand::Share {
+            path,
+            expires_in,
+            password,
+            max_downloads,
+        } => {
+            let url = client.url("share", path.trim_start_matches('/'))?;
+            let request = serde_json::json!({
+                "expires_in": expires_in,
+                "password": password,
+                "max_downloads": max_downloads,
+            });
+            let share: ShareInfo = check(
+                client
+                    .request(Method::POST, url)
+                    .json(&request)
+                    .send()
+                    .await?,
+            )
+            .await?
+            .json()
+            .await?;
+            let mut url = client.url.clone();
+            url.path_segments_mut()
+                .map_err(|_| anyhow!("{} is not a valid server URL", client.url))?
+                .pop_if_empty()
+                .extend(["s", &share.token]);
+            println!("{url}");
+        }
+    }
+    Ok(())
+}
+
+/// Remote folder prefix as the server expects it: no leading slash, trailing slash
+fn folder(path: &str) -> String {
+    let path = path.trim_matches('/');
+    match path.is_empty() {
+        true => String::new(),
+        false => format!("{path}/"),
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+fn progress(size: u64, name: &str) -> ProgressBar {
+    let bar = ProgressBar::new(size);
+    if let Ok(style) =
+        ProgressStyle::with_template("{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} {eta}")
+    {
+        bar.set_style(style.progress_chars("=> "));
+    }
+    bar.set_message(name.to_owned());
+    bar
+}
+
+async fn check(response: Response) -> anyhow::Result<Response> {
+    let status = response.status();
+    if status.is_redirection() {
+        bail!("server requires authentication, set --token / S3CLIX_TOKEN");
+    }
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        bail!("server responded {status}: {text}");
+    }
+    Ok(response)
+}
+
+impl Client {
+    fn url(&self, route: &str, path: &str) -> anyhow::Result<Url> {
+        let mut url = self.url.clone();
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow!("{} is not a valid server URL", self.url))?;
+            segments.pop_if_empty().extend(["api", route]);
+            if !path.is_empty() {
+                segments.extend(path.split('/'));
+            }
+        }
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let mut request = self.cli.request(method, url);
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        if let Some(bucket) = &self.bucket {
+            request = request.header(COOKIE, format!("bucket.name={bucket}"));
+        }
+        request
+    }
+
+    async fn send(&self, method: Method, route: &str, path: &str) -> anyhow::Result<()> {
+        check(self.request(method, self.url(route, path)?).send().await?).await?;
+        Ok(())
+    }
+
+    /// Runs the delete as a server job and follows it until it ends
+    async fn delete_folder(&self, path: &str) -> anyhow::Result<()> {
+        let url = self.url("deleteFolder", path)?;
+        let mut job: JobReport = check(self.request(Method::DELETE, url).send().await?)
+            .await?
+            .json()
+            .await?;
+        let bar = ProgressBar::new(0);
+        if let Ok(style) = ProgressStyle::with_template("{msg} [{bar:30}] {pos}/{len} {eta}") {
+            bar.set_style(style.progress_chars("=> "));
+        }
+        bar.set_message(path.to_owned());
+        let url = self.url("jobs", &job.id)?;
+        while job.status == JobStatus::Running {
+            tokio::time::sleep(JOB_POLL).await;
+            job = check(self.request(Method::GET, url.clone()).send().await?)
+                .await?
+                .json()
+                .await?;
+            bar.set_length(job.total);
+            bar.set_position(job.done);
+        }
+        bar.finish();
+        if let Some(failed) = job.result.as_ref().and_then(|x| x["failed"].as_array()) {
+            failed
+                .iter()
+                .filter_map(|x| x["key"].as_str())
+                .for_each(|x| eprintln!("not deleted: {x}"));
+        }
+        match job.status {
+            JobStatus::Completed => Ok(()),
+            status => bail!("delete is {status:?}: {}", job.error.unwrap_or_default()),
+        }
+    }
+
+    async fn list(&self, path: &str) -> anyhow::Result<Vec<FileList>> {
+        let url = self.url("list", path)?;
+        Ok(check(self.request(Method::GET, url).send().await?)
+            .await?
+            .json()
+            .await?)
+    }
+
+    async fn get(&self, remote: &str, local: &Path) -> anyhow::Result<()> {
+        let url = self.url("download", remote)?;
+        let response = check(self.request(Method::GET, url).send().await?).await?;
+        if let Some(parent) = local.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let bar = progress(response.content_length().unwrap_or_default(), remote);
+        let mut file = tokio::fs::File::create(local).await?;
+        let mut stream = response.bytes_stream();
+        while let Some(data) = stream.next().await {
+            let data = data?;
+            file.write_all(&data).await?;
+            bar.inc(data.len() as u64);
+        }
+        file.flush().await?;
+        bar.finish();
+        Ok(())
+    }
+
+    /// Downloads everything under `remote` prefix into `local` folder
+    async fn get_folder(&self, remote: &str, local: &Path) -> anyhow::Result<()> {
+        let local = local.join(file_name(remote));
+        let mut folders = vec![remote.to_owned()];
+        while let Some(current) = folders.pop() {
+            for file in self.list(&current).await? {
+                if file.folder {
+                    folders.push(file.path);
+                    continue;
+                }
+                if file.name == ".placeholder" {
+                    continue;
+                }
+                let relative = file.path.strip_prefix(remote).unwrap_or(&file.path);
+                self.get(&file.path, &local.join(relative)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn put(&self, local: &Path, remote: &str, overwrite: Option<&str>) -> anyhow::Result<()> {
+        let file = tokio::fs::File::open(local).await?;
+        let size = file.metadata().await?.len();
+        let bar = progress(size, remote);
+        let progress_bar = bar.clone();
+        let body = ReaderStream::new(file).inspect_ok(move |x| progress_bar.inc(x.len() as u64));
+        let mut url = self.url("upload", remote)?;
+        if let Some(overwrite) = overwrite {
+            url.query_pairs_mut().append_pair("overwrite", overwrite);
+        }
+        let response = check(
+            self.request(Method::PUT, url)
+                .header(CONTENT_LENGTH, size)
+                .body(reqwest::Body::wrap_stream(body))
+                .send()
+                .await?,
+        )
+        .await?;
+        bar.finish();
+        if let Some(path) = response
+            .headers()
+            .get("x-upload-path")
+            .and_then(|x| x.to_str().ok())
+        {
+            let path = percent_decode_str(path).decode_utf8_lossy();
+            if path != remote {
+                println!("{remote} uploaded as {path}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads every file under `local` folder to `remote` prefix
+    async fn put_folder(
+        &self,
+        local: &Path,
+        remote: &str,
+        overwrite: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let mut folders = vec![local.to_path_buf()];
+        while let Some(current) = folders.pop() {
+            let mut entries = tokio::fs::read_dir(&current).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    folders.push(path);
+                    continue;
+                }
+                let relative = path
+                    .strip_prefix(local)?
+                    .components()
+                    .map(|x| x.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                self.put(&path, &format!("{remote}{relative}"), overwrite)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -14,37 +14,80 @@ See the License for the specific language governing permissions and
 limitations under the License.
 **/
 use crate::sso::{SSOAuthConfig, SSOConfig};
+use anyhow::{anyhow, bail};
 use log::error;
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::{fs, mem};
+use std::{env, fs, mem};
 use tokio::sync::RwLock;
 
+/// S3 accepts presigned URLs valid for up to a week, seconds
+pub const MAX_PRESIGN_EXPIRY: u64 = 7 * 86400;
+/// Share links and file requests live in memory only, they are never valid longer, seconds
+pub const MAX_SHARE_TTL: u64 = 30 * 86400;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub threads: Option<usize>,
     web: WebConfig,
     auth: AuthConfig,
     pub s3: S3Config,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub tracing: Option<TracingConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Token for scripts and `s3clix client`, sent as `Authorization: Bearer <token>`
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ApiToken {
+    /// acts as username for Header auth
+    pub name: String,
+    #[serde(default)]
+    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub token_file: Option<String>,
+    /// SSO groups the token has
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct WebConfig {
     path: String,
     port: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     ssl: Option<SSLConfig>,
+    /// header a reverse proxy puts the client address in, e.g. `X-Forwarded-For`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    client_ip_header: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct SSLConfig {
     ssl_port: u16,
     certificate: String,
     certificate_key: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TracingConfig {
+    pub endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum AuthConfig {
     None,
@@ -67,6 +110,26 @@ pub struct S3Config {
     pub workers: usize,
     pub upload_memory_pool: usize,
     pub download_memory_pool: usize,
+    #[serde(default = "default_health_interval")]
+    pub health_interval: u64,
+    /// Upload sessions idle longer than this are aborted, seconds
+    #[serde(default = "default_upload_session_ttl")]
+    pub upload_session_ttl: u64,
+    /// Longest validity of share links, seconds. Links live in memory of the process,
+    /// a restart revokes them and other replicas don't know them
+    #[serde(default = "default_share_max_ttl")]
+    pub share_max_ttl: u64,
+    /// Default and largest `max_total_size` of file requests, bytes. Requests live in memory
+    /// of the process like share links, other replicas don't know them
+    #[serde(default = "default_file_request_max_size")]
+    pub file_request_max_size: u64,
+    /// Bytes all uploads and downloads may buffer together, unlimited if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub memory_budget: Option<usize>,
+    /// Seconds a transfer waits for the memory budget before 503
+    #[serde(default = "default_memory_wait")]
+    pub memory_wait: u64,
     pub buckets: Vec<Arc<S3Bucket>>,
 }
 #[derive(Serialize, Deserialize, Debug)]
@@ -75,19 +138,51 @@ pub enum S3UploadType {
     Serial,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub enum HostAccessStyle {
+    #[default]
     Path,
     Subdomain,
 }
 
-impl Default for HostAccessStyle {
-    fn default() -> Self {
-        Self::Path
-    }
+/// Where bucket credentials come from
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum CredentialsSource {
+    /// `access_key` / `secret_key` of the bucket
+    #[default]
+    Static,
+    /// AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and optional AWS_SESSION_TOKEN
+    Env,
+    /// `profile` section (or `default`) of ~/.aws/credentials
+    Profile,
+    /// AWS_ROLE_ARN and AWS_WEB_IDENTITY_TOKEN_FILE, as set by EKS IRSA
+    WebIdentity,
+    /// EC2 instance metadata (IMDSv2, then IMDSv1) or ECS task role
+    InstanceMetadata,
+    /// the first of WebIdentity, Env, Profile and InstanceMetadata which works
+    Default,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Role in another account assumed with the bucket credentials
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AssumeRoleConfig {
+    pub role_arn: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub external_id: Option<String>,
+    #[serde(default = "default_service_name")]
+    pub session_name: String,
+    /// seconds
+    #[serde(default = "default_role_duration")]
+    pub duration: u64,
+    #[serde(default = "default_sts_endpoint")]
+    pub endpoint: String,
+    /// signing region of the endpoint
+    #[serde(default = "default_sts_region")]
+    pub region: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct S3Bucket {
     #[serde(default)]
     pub alias: String,
@@ -98,8 +193,29 @@ pub struct S3Bucket {
     #[serde(default)]
     pub style: HostAccessStyle,
     pub bucket: String,
+    #[serde(default)]
+    pub credentials: CredentialsSource,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub session_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub assume_role: Option<AssumeRoleConfig>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
     pub access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub access_key_file: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
     pub secret_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub secret_key_file: Option<String>,
     pub url: String,
     pub sso_group_prefix: Option<String>,
     #[serde(default = "default_guess_mime")]
@@ -108,8 +224,128 @@ pub struct S3Bucket {
     pub timeout: u64,
     #[serde(default = "default_tries")]
     pub tries: usize,
+    /// browser uploads parts straight to S3 with presigned URLs
+    #[serde(default)]
+    pub direct_upload: bool,
+    /// validity of presigned part URLs of direct uploads, seconds
+    #[serde(default = "default_direct_upload_expiry")]
+    pub direct_upload_expiry: u64,
+    /// parts uploaded through s3clix carry `x-amz-checksum-crc32c` for S3 to verify,
+    /// off for S3-compatible storages without additional checksums
+    #[serde(default = "default_s3_checksums")]
+    pub s3_checksums: bool,
+    /// what uploads do when the file exists, requests may override it
+    #[serde(default)]
+    pub overwrite_policy: OverwritePolicy,
+    /// deletes move files under `.trash/` instead of removing them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub trash: Option<TrashConfig>,
+    /// global search answers from an in-memory index of the keys instead of listing the bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub search_index: Option<SearchIndexConfig>,
+    /// longest validity of URLs from /api/presign, seconds. 0 turns it off
+    #[serde(default = "default_presign_max_expiry")]
+    pub presign_max_expiry: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TrashConfig {
+    /// seconds deleted files are kept for
+    #[serde(default = "default_trash_retention")]
+    pub retention: u64,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchIndexConfig {
+    /// seconds between rescans of the bucket, own writes are indexed at once
+    #[serde(default = "default_rescan_interval")]
+    pub rescan_interval: u64,
+    /// a top-level folder is listed whole at least this often even if it looks unchanged, seconds
+    #[serde(default = "default_full_rescan_interval")]
+    pub full_rescan_interval: u64,
+    /// file the index is kept in between restarts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// What happens when an upload targets an existing file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum OverwritePolicy {
+    /// 409 Conflict
+    #[default]
+    Reject,
+    /// replace the file, needs delete permission
+    Overwrite,
+    /// upload as `name (1).ext`, `name (2).ext`, ...
+    Rename,
+    /// upload as `name-20250101T120000Z.ext`
+    Timestamp,
+}
+
+impl FromStr for OverwritePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "overwrite" => Ok(Self::Overwrite),
+            "rename" => Ok(Self::Rename),
+            "timestamp" => Ok(Self::Timestamp),
+            _ => Err(anyhow!(
+                "unknown overwrite policy {s}, expected Reject, Overwrite, Rename or Timestamp"
+            )),
+        }
+    }
+}
+
+fn default_service_name() -> String {
+    "s3clix".to_owned()
+}
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+fn default_role_duration() -> u64 {
+    3600
+}
+fn default_sts_endpoint() -> String {
+    "https://sts.amazonaws.com/".to_owned()
+}
+fn default_sts_region() -> String {
+    "us-east-1".to_owned()
+}
+fn default_health_interval() -> u64 {
+    60
+}
+fn default_upload_session_ttl() -> u64 {
+    86400
+}
+fn default_share_max_ttl() -> u64 {
+    7 * 86400
+}
+fn default_file_request_max_size() -> u64 {
+    10 << 30
+}
+fn default_memory_wait() -> u64 {
+    30
+}
+fn default_rescan_interval() -> u64 {
+    3600
+}
+fn default_full_rescan_interval() -> u64 {
+    86400
+}
+fn default_direct_upload_expiry() -> u64 {
+    3600
+}
+fn default_presign_max_expiry() -> u64 {
+    3600
+}
+fn default_trash_retention() -> u64 {
+    30 * 86400
+}
 fn default_timeout() -> u64 {
     15
 }
@@ -119,15 +355,107 @@ fn default_tries() -> usize {
 fn default_make_pubic() -> bool {
     false
 }
+fn default_s3_checksums() -> bool {
+    true
+}
+
 fn default_guess_mime() -> bool {
     false
 }
 
+/// Replaces `${NAME}` in string values with the value of NAME environment variable,
+/// `$${` is kept as `${`. Runs on parsed YAML, so a value can't change the structure
+fn interpolate_value(value: &mut Value, path: &str) -> anyhow::Result<()> {
+    match value {
+        Value::String(data) => {
+            *data = interpolate_env(data).map_err(|e| anyhow!("{path}: {e}"))?;
+        }
+        Value::Sequence(items) => {
+            for (idx, item) in items.iter_mut().enumerate() {
+                interpolate_value(item, &format!("{path}[{idx}]"))?;
+            }
+        }
+        Value::Mapping(mapping) => {
+            for (key, item) in mapping.iter_mut() {
+                let key = key.as_str().unwrap_or_default();
+                let path = match path.is_empty() {
+                    true => key.to_owned(),
+                    false => format!("{path}.{key}"),
+                };
+                interpolate_value(item, &path)?;
+            }
+        }
+        Value::Tagged(tagged) => interpolate_value(&mut tagged.value, path)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_env(data: &str) -> anyhow::Result<String> {
+    let mut result = String::with_capacity(data.len());
+    let mut rest = data;
+    while let Some(idx) = rest.find("${") {
+        if rest[..idx].ends_with('$') {
+            result.push_str(&rest[..idx - 1]);
+            result.push_str("${");
+            rest = &rest[idx + 2..];
+            continue;
+        }
+        result.push_str(&rest[..idx]);
+        let Some(end) = rest[idx..].find('}') else {
+            bail!("unterminated ${{");
+        };
+        let name = &rest[idx + 2..idx + end];
+        match env::var(name) {
+            Ok(value) => result.push_str(&value),
+            Err(_) => bail!("environment variable {name} is not set"),
+        }
+        rest = &rest[idx + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Loads secret from `file` if it is set (K8S secret mounts), the secret must be set either way
+fn read_secret(value: &mut String, file: &Option<String>, what: &str) -> anyhow::Result<()> {
+    if let Some(file) = file {
+        if !value.is_empty() {
+            bail!("{what}: both value and file are set, use one of them");
+        }
+        let data = fs::read_to_string(file)
+            .map_err(|e| anyhow!("{what}: cannot read secret file {file}: {e}"))?;
+        *value = data.trim_end_matches(['\r', '\n']).to_owned();
+    }
+    if value.is_empty() {
+        bail!("{what} is not set");
+    }
+    Ok(())
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 impl Config {
     pub fn new<P: AsRef<Path>>(filename: P) -> anyhow::Result<Self> {
-        let fd = fs::File::open(filename)?;
-        let mut config: Self = serde_yaml::from_reader(fd).map_err(anyhow::Error::new)?;
+        let data = fs::read_to_string(&filename)
+            .map_err(|e| anyhow!("cannot read {}: {e}", filename.as_ref().display()))?;
+        // parsed as is first for errors with line and column, interpolation keeps the types
+        serde_yaml::from_str::<Self>(&data).map_err(anyhow::Error::new)?;
+        let mut value: Value = serde_yaml::from_str(&data)?;
+        interpolate_value(&mut value, "")?;
+        let mut config: Self = serde_yaml::from_value(value)?;
 
+        if let AuthConfig::SSOConfig(sso) = &mut config.auth {
+            read_secret(&mut sso.secret, &sso.secret_file, "auth.secret")?;
+        }
+        for token in &mut config.api_tokens {
+            read_secret(
+                &mut token.token,
+                &token.token_file,
+                &format!("api token {}", token.name),
+            )?;
+        }
         config.s3.buckets = config
             .s3
             .buckets
@@ -137,11 +465,72 @@ impl Config {
                 if b.alias.is_empty() {
                     b.alias.clone_from(&b.bucket)
                 }
-                Arc::new(b)
+                if b.credentials == CredentialsSource::Static {
+                    read_secret(
+                        &mut b.access_key,
+                        &b.access_key_file,
+                        &format!("bucket {}: access_key", b.alias),
+                    )?;
+                    read_secret(
+                        &mut b.secret_key,
+                        &b.secret_key_file,
+                        &format!("bucket {}: secret_key", b.alias),
+                    )?;
+                } else if !b.access_key.is_empty()
+                    || !b.secret_key.is_empty()
+                    || b.access_key_file.is_some()
+                    || b.secret_key_file.is_some()
+                {
+                    bail!(
+                        "bucket {}: access_key / secret_key are used with Static credentials only",
+                        b.alias
+                    );
+                }
+                Ok(Arc::new(b))
             })
-            .collect();
+            .collect::<anyhow::Result<_>>()?;
         Ok(config)
     }
+    /// Mistakes which parse fine but break serving: duplicate aliases and SSO settings without SSO
+    pub fn lint(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.s3.share_max_ttl > MAX_SHARE_TTL {
+            problems.push(format!(
+                "share_max_ttl is above {MAX_SHARE_TTL}, share links and file requests expire earlier"
+            ));
+        }
+        for (idx, bucket) in self.s3.buckets.iter().enumerate() {
+            if self.s3.buckets[..idx]
+                .iter()
+                .any(|x| x.alias == bucket.alias)
+            {
+                problems.push(format!(
+                    "bucket alias {} is used more than once, only the first bucket is reachable",
+                    bucket.alias
+                ));
+            }
+            if bucket.direct_upload_expiry > MAX_PRESIGN_EXPIRY {
+                problems.push(format!(
+                    "bucket {}: direct_upload_expiry is above {MAX_PRESIGN_EXPIRY}, S3 rejects such URLs",
+                    bucket.alias
+                ));
+            }
+            if bucket.presign_max_expiry > MAX_PRESIGN_EXPIRY {
+                problems.push(format!(
+                    "bucket {}: presign_max_expiry is above {MAX_PRESIGN_EXPIRY}, S3 rejects such URLs",
+                    bucket.alias
+                ));
+            }
+            if bucket.sso_group_prefix.is_some() && !self.is_sso() {
+                problems.push(format!(
+                    "bucket {}: sso_group_prefix has no effect without SSO auth",
+                    bucket.alias
+                ));
+            }
+        }
+        problems
+    }
+
     pub fn get_web_path(&self) -> &str {
         self.web.path.as_ref()
     }
@@ -156,6 +545,10 @@ impl Config {
         self.web.port
     }
 
+    pub fn get_client_ip_header(&self) -> Option<&str> {
+        self.web.client_ip_header.as_deref()
+    }
+
     pub fn get_ssl(&self) -> (bool, Option<&str>, Option<&str>) {
         match self.web.ssl.as_ref() {
             None => (false, None, None),
@@ -188,10 +581,15 @@ impl Config {
                 let auth = Arc::new(RwLock::new(config.into()));
                 SSOConfig::update(auth.clone()).await?;
                 self.auth = AuthConfig::SSOAuth(auth.clone());
+                // the loop ends once configuration reload drops this SSO
+                let auth = Arc::downgrade(&auth);
                 tokio::spawn(async move {
                     loop {
                         tokio::time::sleep(tokio::time::Duration::from_secs(3600)).await;
-                        if let Err(e) = SSOConfig::update(auth.clone()).await {
+                        let Some(auth) = auth.upgrade() else {
+                            break;
+                        };
+                        if let Err(e) = SSOConfig::update(auth).await {
                             error!("SSO update failed: {e}");
                         }
                     }
@@ -205,6 +603,34 @@ impl Config {
         Ok(())
     }
 
+    /// Takes over already running SSO of the previous configuration if SSO settings are the same
+    pub async fn reuse_sso(&mut self, previous: &Config) -> bool {
+        let (AuthConfig::SSOConfig(config), AuthConfig::SSOAuth(auth)) =
+            (&self.auth, &previous.auth)
+        else {
+            return false;
+        };
+        if auth.read().await.auth_config() != config.as_ref() {
+            return false;
+        }
+        self.auth = AuthConfig::SSOAuth(auth.clone());
+        true
+    }
+
+    /// Settings which are used once on startup
+    pub fn restart_required(&self, previous: &Config) -> bool {
+        self.threads != previous.threads
+            || self.web != previous.web
+            || self.tracing != previous.tracing
+            || self.s3.memory_budget != previous.s3.memory_budget
+    }
+
+    pub fn api_token(&self, token: &str) -> Option<&ApiToken> {
+        self.api_tokens
+            .iter()
+            .find(|x| constant_time_eq(x.token.as_bytes(), token.as_bytes()))
+    }
+
     pub fn is_admin(&self, username: &str) -> bool {
         match &self.auth {
             AuthConfig::Header(header) => header.admins.iter().any(|x| x.as_str().eq(username)),
@@ -217,7 +643,9 @@ impl Config {
 mod test {
     use std::sync::Arc;
 
-    use crate::config::{Config, S3Bucket};
+    use serde_yaml::Value;
+
+    use crate::config::{interpolate_value, Config, S3Bucket};
     use crate::sso::SSOAuthConfig;
 
     #[test]
@@ -228,12 +656,14 @@ mod test {
                 path: "web".to_string(),
                 port: 8080,
                 ssl: None,
+                client_ip_header: None,
             },
             auth: crate::config::AuthConfig::SSOConfig(Box::new(SSOAuthConfig {
                 redirect: "http://localhost:8080/_redirect/".to_string(),
                 resource: "s3clix".to_string(),
                 client_id: "s3clix".to_string(),
                 secret: "XXXXXXXXXXXXXXX".to_string(),
+                secret_file: None,
                 username_claim: "username".to_string(),
                 groups_claim: "group".to_string(),
                 cookie_name: "sessionid".to_string(),
@@ -250,23 +680,76 @@ mod test {
                 workers: 0,
                 upload_memory_pool: 0,
                 download_memory_pool: 0,
+                health_interval: 60,
+                upload_session_ttl: 86400,
+                share_max_ttl: 7 * 86400,
+                file_request_max_size: 10 << 30,
+                memory_budget: None,
+                memory_wait: 30,
                 buckets: vec![Arc::new(S3Bucket {
                     alias: "".to_string(),
                     cdn_url: "".to_string(),
                     make_public: false,
                     style: Default::default(),
                     bucket: "xxx".to_string(),
+                    credentials: Default::default(),
+                    profile: None,
+                    session_name: None,
+                    assume_role: None,
                     access_key: "xxx".to_string(),
+                    access_key_file: None,
                     secret_key: "xxxx".to_string(),
+                    secret_key_file: None,
                     url: "xxxx".to_string(),
                     sso_group_prefix: None,
                     guess_mime: false,
                     timeout: 10,
                     tries: 2,
+                    direct_upload: false,
+                    direct_upload_expiry: 3600,
+                    s3_checksums: true,
+                    overwrite_policy: Default::default(),
+                    trash: None,
+                    search_index: None,
+                    presign_max_expiry: 3600,
                 })],
             },
+            tracing: None,
+            api_tokens: vec![],
         };
         let yml = serde_yaml::to_string(&conf).unwrap();
         let _: Config = serde_yaml::from_str(&yml).unwrap();
     }
+
+    #[test]
+    fn test_interpolate_env() {
+        std::env::set_var("S3CLIX_TEST_SECRET", "s3cr3t");
+        let yml = "secret_key: \"${S3CLIX_TEST_SECRET}\"\n# ${NOT_SET_AT_ALL}\nliteral: $${HOME}\n";
+        let mut value: Value = serde_yaml::from_str(yml).unwrap();
+        interpolate_value(&mut value, "").unwrap();
+        assert_eq!(value["secret_key"], "s3cr3t");
+        assert_eq!(value["literal"], "${HOME}");
+        let mut value: Value =
+            serde_yaml::from_str("a: 1\nb:\n  - c: ${S3CLIX_NOT_SET_AT_ALL}").unwrap();
+        let err = interpolate_value(&mut value, "").unwrap_err();
+        assert!(err.to_string().contains("S3CLIX_NOT_SET_AT_ALL"));
+        assert!(err.to_string().contains("b[0].c"));
+    }
+
+    #[test]
+    fn test_interpolate_yaml_syntax() {
+        let secrets = [
+            ("S3CLIX_TEST_HASH", "abc #def"),
+            ("S3CLIX_TEST_NEWLINE", "abc\ninjected: true"),
+            ("S3CLIX_TEST_QUOTE", "a\": b'c"),
+        ];
+        for (name, secret) in secrets {
+            std::env::set_var(name, secret);
+            let yml = format!("secret_key: ${{{name}}}\nother: 1\n");
+            let mut value: Value = serde_yaml::from_str(&yml).unwrap();
+            interpolate_value(&mut value, "").unwrap();
+            assert_eq!(value["secret_key"], secret);
+            assert_eq!(value.as_mapping().unwrap().len(), 2);
+        }
+    }
 }
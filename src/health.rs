@@ -0,0 +1,265 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::s3::{FailedBucket, S3Client};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BucketStatus {
+    Unknown,
+    Ok,
+    Unreachable,
+    /// never initialised successfully, not served until it recovers
+    Degraded,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BucketHealth {
+    pub bucket: String,
+    pub status: BucketStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checked_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+const INIT_BACKOFF_MIN: u64 = 5;
+const INIT_BACKOFF_MAX: u64 = 300;
+
+struct Entry {
+    client: Weak<S3Client>,
+    health: BucketHealth,
+}
+
+/// Cached results of background bucket checks, so probes never hit S3 themselves
+pub struct Health {
+    buckets: RwLock<Vec<Entry>>,
+    interval: AtomicU64,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            buckets: RwLock::new(Vec::new()),
+            interval: AtomicU64::new(60),
+        }
+    }
+
+    pub async fn buckets(&self) -> Vec<BucketHealth> {
+        self.buckets
+            .read()
+            .await
+            .iter()
+            .map(|x| x.health.clone())
+            .collect()
+    }
+
+    pub async fn status(&self, client: &Arc<S3Client>) -> BucketStatus {
+        self.find_status(client)
+            .await
+            .unwrap_or(BucketStatus::Unknown)
+    }
+
+    /// Status of a bucket by alias, also of buckets without a client
+    pub async fn alias_status(&self, alias: &str) -> Option<BucketStatus> {
+        self.buckets
+            .read()
+            .await
+            .iter()
+            .find(|x| x.health.bucket == alias)
+            .map(|x| x.health.status.clone())
+    }
+
+    async fn find_status(&self, client: &Arc<S3Client>) -> Option<BucketStatus> {
+        self.buckets
+            .read()
+            .await
+            .iter()
+            .find(|x| x.client.as_ptr() == Arc::as_ptr(client))
+            .map(|x| x.health.status.clone())
+    }
+
+    /// Switches to a new set of clients. Clients already known keep their state and checker,
+    /// new ones get the initial check (failing buckets are marked degraded instead of failing
+    /// the server) and a checker of their own. Checkers of dropped clients stop by themselves
+    pub async fn update(
+        self: &Arc<Self>,
+        clients: &[Arc<S3Client>],
+        failed: &[FailedBucket],
+        interval: u64,
+    ) {
+        self.interval.store(interval, Ordering::Relaxed);
+        let mut fresh = Vec::new();
+        {
+            let mut buckets = self.buckets.write().await;
+            let mut old = std::mem::take(&mut *buckets);
+            for client in clients {
+                match old
+                    .iter()
+                    .position(|x| x.client.as_ptr() == Arc::as_ptr(client))
+                {
+                    Some(idx) => buckets.push(old.swap_remove(idx)),
+                    None => {
+                        buckets.push(Entry {
+                            client: Arc::downgrade(client),
+                            health: BucketHealth {
+                                bucket: client.config.alias.clone(),
+                                status: BucketStatus::Unknown,
+                                checked_at: None,
+                                latency_ms: None,
+                                error: None,
+                            },
+                        });
+                        fresh.push(client.clone());
+                    }
+                }
+            }
+            // no client to check, they stay degraded until the next reload
+            for bucket in failed {
+                buckets.push(Entry {
+                    client: Weak::new(),
+                    health: BucketHealth {
+                        bucket: bucket.alias.clone(),
+                        status: BucketStatus::Degraded,
+                        checked_at: Some(unix_now()),
+                        latency_ms: None,
+                        error: Some(bucket.error.clone()),
+                    },
+                });
+            }
+        }
+        for client in fresh {
+            if !self.check(&client, BucketStatus::Degraded).await {
+                warn!(
+                    "Bucket {} failed to initialise, serving it is postponed",
+                    client.config.alias
+                );
+            }
+            self.spawn(Arc::downgrade(&client));
+        }
+    }
+
+    async fn check(&self, client: &Arc<S3Client>, failure: BucketStatus) -> bool {
+        let started = Instant::now();
+        let result = client.ping().await;
+        let latency_ms = started.elapsed().as_millis() as u64;
+        let mut buckets = self.buckets.write().await;
+        let Some(entry) = buckets
+            .iter_mut()
+            .find(|x| x.client.as_ptr() == Arc::as_ptr(client))
+        else {
+            return false;
+        };
+        let health = &mut entry.health;
+        health.checked_at = Some(unix_now());
+        health.latency_ms = Some(latency_ms);
+        match result {
+            Ok(_) => {
+                if health.status == BucketStatus::Degraded {
+                    info!("Bucket {} recovered and is served now", health.bucket);
+                }
+                health.status = BucketStatus::Ok;
+                health.error = None;
+                true
+            }
+            Err(e) => {
+                warn!("Health check for bucket {} failed: {e}", health.bucket);
+                health.status = failure;
+                health.error = Some(e.to_string());
+                false
+            }
+        }
+    }
+
+    /// Degraded buckets are retried with backoff, healthy ones are checked every `interval` seconds
+    fn spawn(self: &Arc<Self>, client: Weak<S3Client>) {
+        let health = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut backoff = INIT_BACKOFF_MIN;
+            loop {
+                let (sleep, failure) = {
+                    let (Some(health), Some(client)) = (health.upgrade(), client.upgrade()) else {
+                        break;
+                    };
+                    match health.find_status(&client).await {
+                        // client was replaced by reload
+                        None => break,
+                        Some(BucketStatus::Degraded) => {
+                            let sleep = backoff;
+                            backoff = (backoff * 2).min(INIT_BACKOFF_MAX);
+                            (sleep, BucketStatus::Degraded)
+                        }
+                        Some(_) => (
+                            health.interval.load(Ordering::Relaxed),
+                            BucketStatus::Unreachable,
+                        ),
+                    }
+                };
+                tokio::time::sleep(Duration::from_secs(sleep)).await;
+                let (Some(health), Some(client)) = (health.upgrade(), client.upgrade()) else {
+                    break;
+                };
+                health.check(&client, failure).await;
+            }
+        });
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_bucket() {
+        let health = Arc::new(Health::new());
+        let failed = FailedBucket {
+            alias: "broken".to_owned(),
+            error: "bad url".to_owned(),
+        };
+        health.update(&[], &[failed], 60).await;
+        assert_eq!(
+            health.alias_status("broken").await,
+            Some(BucketStatus::Degraded)
+        );
+        let buckets = health.buckets().await;
+        assert_eq!(buckets[0].error.as_deref(), Some("bad url"));
+        // fixed by reload
+        health.update(&[], &[], 60).await;
+        assert_eq!(health.alias_status("broken").await, None);
+    }
+}
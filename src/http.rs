@@ -13,104 +13,198 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.
 **/
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use anyhow::anyhow;
-use axum::extract::{BodyStream, Path, Query, State};
-use axum::http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
-use axum::http::{HeaderMap, Request, StatusCode};
-use axum::middleware::{from_fn_with_state, Next};
-use axum::response::{IntoResponse, Redirect, Response};
+use axum::body::Bytes;
+use axum::extract::{BodyStream, ConnectInfo, MatchedPath, Path, Query, State};
+use axum::http::header::{
+    AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER,
+};
+use axum::http::{HeaderMap, HeaderValue, Request, StatusCode};
+use axum::middleware::{from_fn, from_fn_with_state, Next};
+use axum::response::{Html, IntoResponse, Redirect, Response};
 use axum::routing::*;
 use axum::{Form, Json, Router};
 use axum_extra::body::AsyncReadBody;
 use axum_extra::extract::cookie::Cookie;
 use axum_extra::extract::CookieJar;
 use axum_server::tls_rustls::RustlsConfig;
-use log::{debug, info, warn};
-use serde::Deserialize;
+use futures_util::{Stream, StreamExt};
+use log::{debug, error, info, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use time::macros::format_description;
+use time::OffsetDateTime;
 use tokio::io::duplex;
 use tokio::spawn;
+use tokio::sync::RwLock;
 use tower_http::services::ServeDir;
+use tracing::{instrument, trace_span, Instrument};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use crate::config::{AuthConfig, Config, S3UploadType};
-use crate::s3::S3Client;
-use crate::sso::RedirectCode;
+use crate::checksum::{ChecksumMismatch, Checksums, Hasher, CRC32C_HEADER, SHA256_HEADER};
+use crate::config::{
+    AuthConfig, Config, OverwritePolicy, S3UploadType, MAX_PRESIGN_EXPIRY, MAX_SHARE_TTL,
+};
+use crate::health::{BucketHealth, BucketStatus, Health};
+use crate::jobs::{JobFailed, Jobs};
+use crate::memory::{MemoryExhausted, MEMORY};
+use crate::metrics::{DownloadGuard, METRICS};
+use crate::reload;
+use crate::s3::{NoCredentials, ObjectExists, PresignMethod, S3Client, UploadedPart};
+use crate::search::{BadSearch, SearchQuery, TRUNCATED_HEADER};
+use crate::session::{SessionPart, UploadSessions, MAX_PART_NUMBER};
+use crate::share::{RequestLimit, ShareGone, ShareNotFound, SharePassword, ShareThrottled, Shares};
+use crate::sso::{Action, RedirectCode, SSOStatus};
+use crate::telemetry;
+use crate::trash::{in_trash, TrashConflict, TrashNotFound};
 
 pub struct HttpServer;
 
 struct AppState {
-    s3: Vec<Arc<S3Client>>,
+    current: RwLock<Arc<Snapshot>>,
+    health: Arc<Health>,
+    sessions: Arc<UploadSessions>,
+    jobs: Arc<Jobs>,
+    shares: Arc<Shares>,
+}
+
+/// Everything which is replaced at once on configuration reload
+struct Snapshot {
     config: Arc<Config>,
+    s3: Vec<Arc<S3Client>>,
 }
 
 impl AppState {
-    fn get_s3(&self, bucket: Option<&str>) -> Option<Arc<S3Client>> {
+    async fn config(&self) -> Arc<Config> {
+        self.current.read().await.config.clone()
+    }
+
+    async fn get_s3(&self, bucket: Option<&str>) -> Option<Arc<S3Client>> {
+        let current = self.current.read().await;
         match bucket {
-            None => self.s3.first().cloned(),
-            Some(str) => self.s3.iter().find(|x| x.config.alias.eq(str)).cloned(),
+            None => current.s3.first().cloned(),
+            Some(str) => current.s3.iter().find(|x| x.config.alias.eq(str)).cloned(),
         }
     }
 
     async fn get_s3_from_jar(&self, jar: CookieJar) -> Result<Arc<S3Client>, Response> {
         let value = jar.get(BUCKET_NAME).map(|x| x.value());
-        match self.get_s3(value) {
-            None => Err((StatusCode::NOT_FOUND, "s3 bucket not found").into_response()),
-            Some(s3client) => Ok(s3client),
+        self.get_available_s3(value).await
+    }
+
+    /// Bucket client, 404 if there is no such bucket and 503 if it's degraded
+    async fn get_available_s3(&self, bucket: Option<&str>) -> Result<Arc<S3Client>, Response> {
+        match self.get_s3(bucket).await {
+            None => {
+                let failed = match bucket {
+                    Some(alias) => self.health.alias_status(alias).await.is_some(),
+                    None => false,
+                };
+                if failed {
+                    return Err(
+                        (StatusCode::SERVICE_UNAVAILABLE, "s3 bucket is unavailable")
+                            .into_response(),
+                    );
+                }
+                Err((StatusCode::NOT_FOUND, "s3 bucket not found").into_response())
+            }
+            Some(s3client) => {
+                if self.health.status(&s3client).await == BucketStatus::Degraded {
+                    return Err(
+                        (StatusCode::SERVICE_UNAVAILABLE, "s3 bucket is unavailable")
+                            .into_response(),
+                    );
+                }
+                Ok(s3client)
+            }
+        }
+    }
+
+    /// Parses and validates the configuration file again, then swaps it in.
+    /// Unchanged S3 clients and SSO configuration are kept, so running transfers are not affected
+    async fn reload(&self, path: &str) -> anyhow::Result<()> {
+        let mut config = Config::new(path)?;
+        let current = self.current.read().await.clone();
+        if !config.reuse_sso(&current.config).await && config.is_sso() {
+            config.init_sso().await?;
+        }
+        if config.restart_required(&current.config) {
+            warn!("Changes of threads, web, tracing or memory_budget settings are applied after restart only");
         }
+        let config = Arc::new(config);
+        let (s3, failed) = S3Client::reload(&config, &current.s3).await;
+        *self.current.write().await = Arc::new(Snapshot {
+            config: config.clone(),
+            s3: s3.clone(),
+        });
+        self.health
+            .update(&s3, &failed, config.s3.health_interval)
+            .await;
+        self.sessions.set_ttl(config.s3.upload_session_ttl);
+        MEMORY.set_wait(config.s3.memory_wait);
+        Ok(())
     }
 }
 impl HttpServer {
-    pub async fn start(config: Arc<Config>) -> anyhow::Result<()> {
+    pub async fn start(config: Arc<Config>, config_path: String) -> anyhow::Result<()> {
         debug!("HttpServer::start");
-        let s3 = S3Client::new(config.clone()).await?;
+        MEMORY.init(config.s3.memory_budget, config.s3.memory_wait);
+        let (s3, failed) = S3Client::new(config.clone()).await;
+        let health = Arc::new(Health::new());
+        health.update(&s3, &failed, config.s3.health_interval).await;
         let state = Arc::new(AppState {
-            s3: s3.into_iter().map(Arc::new).collect(),
-            config: config.clone(),
+            current: RwLock::new(Arc::new(Snapshot {
+                config: config.clone(),
+                s3,
+            })),
+            health,
+            sessions: UploadSessions::new(config.s3.upload_session_ttl),
+            jobs: Jobs::new(),
+            shares: Shares::new(),
         });
-        let delete_api = Router::new()
-            .route("/deleteFolder/*path", delete(delete_folder))
-            .route("/delete/*path", delete(del))
-            .with_state(state.clone())
-            .layer(from_fn_with_state(state.clone(), can_delete));
-        let upload_api = Router::new()
-            .route("/upload/*path", put(upload))
-            .route("/mkdir/*path", post(mkdir))
-            .with_state(state.clone())
-            .layer(from_fn_with_state(state.clone(), can_upload));
-        let api = Router::new()
-            .route("/is_admin", get(check_can_delete))
-            .route("/can_delete", get(check_can_delete))
-            .route("/can_upload", get(check_can_upload))
-            .route("/buckets", get(buckets))
-            .route("/bucket", post(select_bucket))
-            .route("/list", get(root))
-            .route("/list/", get(root))
-            .route("/search", get(search))
-            .route("/list/*path", get(list))
-            .merge(delete_api)
-            .merge(upload_api)
-            .route("/download/*path", get(download))
-            .with_state(state.clone());
+        let reload_state = state.clone();
+        reload::watch(config_path.clone(), move || {
+            let state = reload_state.clone();
+            let path = config_path.clone();
+            async move {
+                match state.reload(&path).await {
+                    Ok(_) => info!("Configuration reloaded from {path}"),
+                    Err(e) => error!("Configuration reload rejected: {e}"),
+                }
+            }
+        });
+        let api = api_router(state.clone());
 
         let mut web_root = Router::new()
             .route_service("/", ServeDir::new(config.get_web_path()))
             .route_service("/*any", ServeDir::new(config.get_web_path()))
             .nest("/api", api)
             .layer(from_fn_with_state(state.clone(), auth_middleware));
-        if state.config.is_sso() {
-            web_root = Router::new()
-                .route("/_redirect", get(redirect))
-                .route("/_redirect/", get(redirect))
-                .with_state(state.clone())
-                .nest("/", web_root);
-        }
-        // Add readiness probe
+        // registered regardless of auth type, as SSO may be turned on by reload
         web_root = Router::new()
-            .route("/ready", get(readiness))
+            .route("/_redirect", get(redirect))
+            .route("/_redirect/", get(redirect))
+            .route("/s/:token", get(share_download).post(share_unlock))
+            .route("/r/:token", get(file_request_page))
+            .route("/r/:token/:name", put(file_request_upload))
             .with_state(state.clone())
             .nest("/", web_root);
+        // Add probes and metrics
+        web_root = Router::new()
+            .route("/ready", get(readiness))
+            .route("/live", get(liveness))
+            .route("/health", get(health_report))
+            .route("/metrics", get(metrics))
+            .with_state(state.clone())
+            .nest("/", web_root)
+            .layer(from_fn(track_metrics))
+            .layer(from_fn(trace_request));
 
         // we do check SSL & cert/key in config before
         let address = format!("0.0.0.0:{}", config.get_web_port())
@@ -127,24 +221,80 @@ impl HttpServer {
                     .await
                     .map_err(|x| anyhow!(x))?;
                 axum_server::bind_rustls(address, tls_config)
-                    .serve(web_root.into_make_service())
+                    .serve(web_root.into_make_service_with_connect_info::<SocketAddr>())
                     .await
                     .map_err(|x| anyhow!(x))
             }
             false => axum_server::bind(address)
-                .serve(web_root.into_make_service())
+                .serve(web_root.into_make_service_with_connect_info::<SocketAddr>())
                 .await
                 .map_err(|x| anyhow!(x)),
         }
     }
 }
 
+/// Routes under `/api`, `auth_middleware` is layered on top of them
+fn api_router(state: Arc<AppState>) -> Router {
+    let delete_api = Router::new()
+        .route("/deleteFolder/*path", delete(delete_folder))
+        .route("/delete/*path", delete(del))
+        .route("/trash/:id", delete(trash_purge))
+        .with_state(state.clone())
+        .layer(from_fn_with_state(state.clone(), can_delete));
+    let upload_api = Router::new()
+        .route("/upload/*path", put(upload))
+        .route("/mkdir/*path", post(mkdir))
+        .route("/directUpload/*path", post(direct_upload))
+        .route("/directComplete/*path", post(direct_complete))
+        .route("/directAbort/*path", post(direct_abort))
+        .route("/uploadSessions/*path", post(create_session))
+        .route(
+            "/uploadSession/:id",
+            get(session_status).delete(session_abort),
+        )
+        .route("/uploadSession/:id/complete", post(session_complete))
+        .route("/uploadSession/:id/:part", put(session_part))
+        .route("/restoreVersion/*path", post(restore_version))
+        .route("/trash/:id/restore", post(trash_restore))
+        .route("/fileRequest/*path", post(file_request_create))
+        .with_state(state.clone())
+        .layer(from_fn_with_state(state.clone(), can_upload));
+    Router::new()
+        .route("/is_admin", get(check_can_delete))
+        .route("/can_delete", get(check_can_delete))
+        .route("/can_upload", get(check_can_upload))
+        .route("/buckets", get(buckets))
+        .route("/bucket", post(select_bucket))
+        .route("/list", get(root))
+        .route("/list/", get(root))
+        .route("/search", get(search))
+        .route("/list/*path", get(list))
+        .merge(delete_api)
+        .merge(upload_api)
+        .route("/download/*path", get(download))
+        .route("/checksum/*path", get(checksum))
+        .route("/presign/*path", get(presign))
+        .route("/versions", get(versions_root))
+        .route("/versions/*path", get(versions))
+        .route("/trash", get(trash_list))
+        .route("/jobs", get(job_list))
+        .route("/jobs/:id", get(job_status).delete(job_cancel))
+        .route("/share/*path", post(share_create))
+        .route("/shares", get(share_list))
+        .route("/shares/:token", delete(share_revoke))
+        .route("/fileRequests", get(file_request_list))
+        .route("/fileRequests/:token", delete(file_request_revoke))
+        .route_layer(from_fn(reject_trash_paths))
+        .with_state(state)
+}
+
 async fn redirect(
     State(state): State<Arc<AppState>>,
     jar: CookieJar,
     query: Query<RedirectCode>,
 ) -> Response {
-    match state.config.get_auth_config() {
+    let config = state.config().await;
+    match config.get_auth_config() {
         AuthConfig::SSOAuth(auth_config) => {
             let auth_config = auth_config.read().await;
             match auth_config.code_exchange(&query.0).await {
@@ -179,15 +329,29 @@ async fn redirect(
     }
 }
 
-async fn buckets(State(state): State<Arc<AppState>>, _jar: CookieJar) -> Response {
+#[derive(Deserialize)]
+struct BucketsQuery {
+    #[serde(default)]
+    details: bool,
+}
+
+async fn buckets(
+    State(state): State<Arc<AppState>>,
+    _jar: CookieJar,
+    Query(query): Query<BucketsQuery>,
+) -> Response {
     // TODO: filter out buckets user can't see
+    let buckets = state.health.buckets().await;
+    if query.details {
+        return (StatusCode::OK, Json(buckets)).into_response();
+    }
     (
         StatusCode::OK,
         Json(
-            state
-                .s3
-                .iter()
-                .map(|x| x.config.alias.clone())
+            buckets
+                .into_iter()
+                .filter(|x| x.status != BucketStatus::Degraded)
+                .map(|x| x.bucket)
                 .collect::<Vec<String>>(),
         ),
     )
@@ -195,11 +359,21 @@ async fn buckets(State(state): State<Arc<AppState>>, _jar: CookieJar) -> Respons
 }
 
 async fn readiness(State(state): State<Arc<AppState>>) -> Response {
-    if state.config.s3.buckets.len() != state.s3.len() {
-        return (StatusCode::SERVICE_UNAVAILABLE, "S3 is not ready").into_response();
+    let config = state.config().await;
+    let buckets = config.s3.buckets.len();
+    let degraded = state
+        .health
+        .buckets()
+        .await
+        .into_iter()
+        .filter(|x| x.status == BucketStatus::Degraded)
+        .map(|x| x.bucket)
+        .collect::<Vec<String>>();
+    if degraded.len() == buckets {
+        return (StatusCode::SERVICE_UNAVAILABLE, "No S3 bucket is available").into_response();
     }
-    if state.config.is_sso() {
-        match state.config.get_auth_config() {
+    if config.is_sso() {
+        match config.get_auth_config() {
             AuthConfig::SSOAuth(auth) => {
                 let auth_config = auth.read().await;
                 if !auth_config.ready() {
@@ -219,8 +393,98 @@ async fn readiness(State(state): State<Arc<AppState>>) -> Response {
             }
         }
     }
+    if !degraded.is_empty() {
+        return (
+            StatusCode::OK,
+            format!("Service ready, degraded buckets: {}", degraded.join(", ")),
+        )
+            .into_response();
+    }
     (StatusCode::OK, "Service ready").into_response()
 }
+async fn liveness() -> Response {
+    (StatusCode::OK, "Service alive").into_response()
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    healthy: bool,
+    buckets: Vec<BucketHealth>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sso: Option<SSOStatus>,
+}
+
+async fn health_report(State(state): State<Arc<AppState>>) -> Response {
+    let config = state.config().await;
+    let buckets = state.health.buckets().await;
+    let sso = match config.get_auth_config() {
+        AuthConfig::SSOAuth(auth) => Some(auth.read().await.status()),
+        _ => None,
+    };
+    let healthy = buckets
+        .iter()
+        .all(|x| matches!(x.status, BucketStatus::Ok | BucketStatus::Unknown))
+        && sso.as_ref().map(|x| x.ready).unwrap_or(true);
+    let status = match healthy {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (
+        status,
+        Json(HealthReport {
+            healthy,
+            buckets,
+            sso,
+        }),
+    )
+        .into_response()
+}
+
+async fn metrics() -> Response {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+        .into_response()
+}
+
+fn route_label<B>(req: &Request<B>) -> String {
+    req.extensions()
+        .get::<MatchedPath>()
+        .map(|x| x.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_owned())
+}
+
+async fn track_metrics<B>(req: Request<B>, next: Next<B>) -> Response {
+    let route = route_label(&req);
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let response = next.run(req).await;
+    let status = response.status();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    METRICS.http_requests.with_label_values(&labels).inc();
+    METRICS
+        .http_duration
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+async fn trace_request<B>(req: Request<B>, next: Next<B>) -> Response {
+    let route = route_label(&req);
+    let span = trace_span!(
+        "http",
+        otel.name = format!("{} {}", req.method(), route),
+        http.method = %req.method(),
+        http.route = route,
+        http.status_code = tracing::field::Empty,
+    );
+    span.set_parent(telemetry::extract_context(req.headers()));
+    let response = next.run(req).instrument(span.clone()).await;
+    span.record("http.status_code", response.status().as_u16());
+    response
+}
+
 #[derive(Deserialize)]
 struct BucketQuery {
     bucket: String,
@@ -232,7 +496,7 @@ async fn select_bucket(
     jar: CookieJar,
     Form(bucket): Form<BucketQuery>,
 ) -> Response {
-    match state.get_s3(Some(&bucket.bucket)) {
+    match state.get_s3(Some(&bucket.bucket)).await {
         None => (StatusCode::NOT_FOUND, "Bucket not found").into_response(),
         Some(_) => (
             StatusCode::OK,
@@ -271,10 +535,6 @@ async fn list(state: State<Arc<AppState>>, jar: CookieJar, Path(path): Path<Stri
     }
 }
 
-#[derive(Deserialize)]
-struct SearchQuery {
-    pattern: String,
-}
 async fn search(
     state: State<Arc<AppState>>,
     jar: CookieJar,
@@ -284,10 +544,16 @@ async fn search(
         Ok(s3) => s3,
         Err(response) => return response,
     };
-    let list = s3.search(&search.pattern).await;
-    match list {
+    match s3.search(&search).await {
+        Err(e) if e.is::<BadSearch>() => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-        Ok(list) => (StatusCode::OK, Json(list)).into_response(),
+        Ok(search) => {
+            let mut headers = HeaderMap::new();
+            if search.truncated {
+                headers.insert(TRUNCATED_HEADER, HeaderValue::from_static("true"));
+            }
+            (StatusCode::OK, headers, Json(search.files)).into_response()
+        }
     }
 }
 
@@ -295,169 +561,1489 @@ async fn upload(
     state: State<Arc<AppState>>,
     jar: CookieJar,
     Path(path): Path<String>,
+    Query(overwrite): Query<OverwriteQuery>,
+    headers: HeaderMap,
     body: BodyStream,
 ) -> Response {
-    let s3 = match state.get_s3_from_jar(jar).await {
+    let config = state.config().await;
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
         Ok(s3) => s3,
         Err(response) => return response,
     };
-    match s3.exists(&path).await {
-        Ok(true) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "File already exists").into_response();
-        }
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-        _ => {}
+    let expected = match Checksums::expected(&headers) {
+        Ok(expected) => expected,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let path = match upload_target(&state, &s3, path, &overwrite, &headers, &jar).await {
+        Ok(path) => path,
+        Err(response) => return response,
     };
-    let res = match state.config.s3.upload_type {
-        S3UploadType::Parallel => s3.upload_parallel(&path, body).await,
-        S3UploadType::Serial => s3.upload_serial(&path, body).await,
+    let res = match config.s3.upload_type {
+        S3UploadType::Parallel => s3.upload_parallel(&path, body, &expected, false).await,
+        S3UploadType::Serial => s3.upload_serial(&path, body, &expected, false).await,
     };
     match res {
-        Ok(_) => (StatusCode::OK, "OK").into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Ok(checksums) => uploaded(&checksums, &path),
+        Err(e) => transfer_error(e),
     }
 }
 
-async fn mkdir(state: State<Arc<AppState>>, jar: CookieJar, Path(path): Path<String>) -> Response {
-    let s3 = match state.get_s3_from_jar(jar).await {
+/// Answer to a finished upload: checksums and the final path
+fn uploaded(checksums: &Checksums, path: &str) -> Response {
+    let mut headers = digest_headers(checksums);
+    let path = utf8_percent_encode(path, UPLOAD_PATH_ENCODE).to_string();
+    if let Ok(path) = HeaderValue::from_str(&path) {
+        headers.insert(UPLOAD_PATH_HEADER, path);
+    }
+    (headers, "OK").into_response()
+}
+
+/// Final path of an uploaded file, percent-encoded
+const UPLOAD_PATH_HEADER: &str = "x-upload-path";
+/// non-ASCII is always encoded
+const UPLOAD_PATH_ENCODE: &AsciiSet = &CONTROLS.add(b'%');
+const OVERWRITE_HEADER: &str = "x-overwrite-policy";
+/// `name (1).ext` up to this number is tried by `Rename`
+const MAX_RENAMES: usize = 1000;
+
+#[derive(Deserialize)]
+struct OverwriteQuery {
+    overwrite: Option<String>,
+}
+
+/// Path to upload to according to `?overwrite=` / `x-overwrite-policy` or the bucket policy
+async fn upload_target(
+    state: &Arc<AppState>,
+    s3: &S3Client,
+    path: String,
+    query: &OverwriteQuery,
+    headers: &HeaderMap,
+    jar: &CookieJar,
+) -> Result<String, Response> {
+    let policy = overwrite_policy(s3, query, headers)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+    policy_target(state, s3, path, policy, headers, jar).await
+}
+
+/// Policy the request chose, the bucket one by default
+fn overwrite_policy(
+    s3: &S3Client,
+    query: &OverwriteQuery,
+    headers: &HeaderMap,
+) -> anyhow::Result<OverwritePolicy> {
+    let requested = query
+        .overwrite
+        .as_deref()
+        .or(headers.get(OVERWRITE_HEADER).and_then(|x| x.to_str().ok()));
+    match requested {
+        None => Ok(s3.config.overwrite_policy),
+        Some(requested) => OverwritePolicy::from_str(requested),
+    }
+}
+
+/// Checks `path` of an upload started earlier with `policy` again before completing it: a file
+/// created meanwhile is a conflict unless it may be overwritten. Returns whether S3 has to keep
+/// a file created after the check (`create_only`)
+async fn complete_target(
+    state: &Arc<AppState>,
+    s3: &S3Client,
+    path: &str,
+    policy: OverwritePolicy,
+    headers: &HeaderMap,
+    jar: &CookieJar,
+) -> Result<bool, Response> {
+    let target = policy_target(state, s3, path.to_owned(), policy, headers, jar).await?;
+    match target == path {
+        true => Ok(policy != OverwritePolicy::Overwrite),
+        false => Err((StatusCode::CONFLICT, "File already exists").into_response()),
+    }
+}
+
+/// Path to upload to according to the policy if `path` exists
+async fn policy_target(
+    state: &Arc<AppState>,
+    s3: &S3Client,
+    path: String,
+    policy: OverwritePolicy,
+    headers: &HeaderMap,
+    jar: &CookieJar,
+) -> Result<String, Response> {
+    let exists = |path: String| async move {
+        s3.exists(&path)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())
+    };
+    if !exists(path.clone()).await? {
+        return Ok(path);
+    }
+    let conflict = || (StatusCode::CONFLICT, "File already exists").into_response();
+    let (stem, extension) = split_extension(&path);
+    match policy {
+        OverwritePolicy::Reject => Err(conflict()),
+        OverwritePolicy::Overwrite => {
+            match bool_check_can_delete(state.clone(), headers.clone(), jar.clone()).await {
+                true => Ok(path),
+                false => Err((
+                    StatusCode::FORBIDDEN,
+                    "Overwriting a file needs delete permission",
+                )
+                    .into_response()),
+            }
+        }
+        OverwritePolicy::Rename => {
+            for i in 1..=MAX_RENAMES {
+                let candidate = format!("{stem} ({i}){extension}");
+                if !exists(candidate.clone()).await? {
+                    return Ok(candidate);
+                }
+            }
+            Err(conflict())
+        }
+        OverwritePolicy::Timestamp => {
+            let now = OffsetDateTime::now_utc()
+                .format(format_description!(
+                    "[year][month][day]T[hour][minute][second]Z"
+                ))
+                .unwrap_or_default();
+            let candidate = format!("{stem}-{now}{extension}");
+            match exists(candidate.clone()).await? {
+                true => Err(conflict()),
+                false => Ok(candidate),
+            }
+        }
+    }
+}
+
+/// `folder/name.tar.gz` is `folder/name.tar` and `.gz`, dot files have no extension
+fn split_extension(path: &str) -> (&str, &str) {
+    let name_start = path.rfind('/').map(|x| x + 1).unwrap_or_default();
+    match path[name_start..].rfind('.') {
+        Some(0) | None => (path, ""),
+        Some(dot) => path.split_at(name_start + dot),
+    }
+}
+
+#[derive(Deserialize)]
+struct DirectUploadRequest {
+    size: u64,
+}
+
+/// Authorises upload straight to S3: browser gets presigned URL for every part
+async fn direct_upload(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    Query(overwrite): Query<OverwriteQuery>,
+    headers: HeaderMap,
+    Json(request): Json<DirectUploadRequest>,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
         Ok(s3) => s3,
         Err(response) => return response,
     };
-    match s3.mkdir(&path).await {
-        Ok(_) => (StatusCode::OK, "OK").into_response(),
+    if !s3.config.direct_upload {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            "Direct upload is not enabled for the bucket",
+        )
+            .into_response();
+    }
+    let path = match upload_target(&state, &s3, path, &overwrite, &headers, &jar).await {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    match s3.direct_upload(&path, request.size).await {
+        Ok(upload) => (StatusCode::OK, Json(upload)).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
-async fn download(
-    state: State<Arc<AppState>>,
+#[derive(Deserialize)]
+struct DirectCompleteRequest {
+    upload_id: String,
+    #[serde(default)]
+    parts: Vec<UploadedPart>,
+}
+
+/// Permissions are checked by the layer again, the upload id must belong to the path and
+/// the overwrite policy must still allow the path
+async fn direct_complete(
+    State(state): State<Arc<AppState>>,
     jar: CookieJar,
     Path(path): Path<String>,
+    Query(overwrite): Query<OverwriteQuery>,
+    headers: HeaderMap,
+    Json(request): Json<DirectCompleteRequest>,
 ) -> Response {
-    let s3 = match state.get_s3_from_jar(jar).await {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
         Ok(s3) => s3,
         Err(response) => return response,
     };
-    let (file_name, file_size, file_mime) = match s3.prepare_download(&path).await {
-        Err(e) => {
-            return (StatusCode::NOT_FOUND, e.to_string()).into_response();
-        }
-        Ok(x) => x,
+    match s3.has_upload(&path, &request.upload_id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "Upload not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+    let policy = match overwrite_policy(&s3, &overwrite, &headers) {
+        Ok(policy) => policy,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     };
-    let (tx, rx) = duplex(state.config.s3.download_memory_pool);
-    spawn(async move {
-        if let Err(e) = s3.download(&path, tx).await {
-            warn!("Error while downloading file: {}", e);
-        }
-    });
-    let body = AsyncReadBody::new(rx);
-    let headers = [
-        (CONTENT_TYPE, file_mime),
-        (
-            CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", file_name),
-        ),
-        (CONTENT_LENGTH, file_size.to_string()),
-    ];
-    (headers, body).into_response()
+    let create_only = match complete_target(&state, &s3, &path, policy, &headers, &jar).await {
+        Ok(create_only) => create_only,
+        Err(response) => return response,
+    };
+    match s3
+        .complete_upload(&path, &request.upload_id, request.parts, None, create_only)
+        .await
+    {
+        Ok(_) => (StatusCode::OK, "OK").into_response(),
+        Err(e) => transfer_error(e),
+    }
 }
 
-async fn del(state: State<Arc<AppState>>, jar: CookieJar, Path(path): Path<String>) -> Response {
+async fn direct_abort(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    Json(request): Json<DirectCompleteRequest>,
+) -> Response {
     let s3 = match state.get_s3_from_jar(jar).await {
         Ok(s3) => s3,
         Err(response) => return response,
     };
-    match s3.delete(&path).await {
+    match s3.has_upload(&path, &request.upload_id).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "Upload not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+    match s3.abort_upload(&path, &request.upload_id).await {
         Ok(_) => (StatusCode::OK, "OK").into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
-async fn delete_folder(
+/// Starts resumable upload, the returned id is used for the rest of `/uploadSession` routes
+async fn create_session(
     state: State<Arc<AppState>>,
     jar: CookieJar,
     Path(path): Path<String>,
+    Query(overwrite): Query<OverwriteQuery>,
+    headers: HeaderMap,
 ) -> Response {
-    let s3 = match state.get_s3_from_jar(jar).await {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
         Ok(s3) => s3,
         Err(response) => return response,
     };
-    match s3.delete_prefix(&path).await {
-        Ok(_) => (StatusCode::OK, "OK").into_response(),
+    let policy = match overwrite_policy(&s3, &overwrite, &headers) {
+        Ok(policy) => policy,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let path = match policy_target(&state, &s3, path, policy, &headers, &jar).await {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    let user = request_user(&state, &headers, &jar).await;
+    match state.sessions.create(s3, &path, &user, policy).await {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
-// AUTH
-
-async fn bool_check_can_delete(state: Arc<AppState>, headers: HeaderMap, jar: CookieJar) -> bool {
-    match state.config.get_auth_config() {
-        AuthConfig::None => true,
-        AuthConfig::Header(_) => {
-            let username = headers
-                .get(state.config.get_header_auth_header())
-                .unwrap()
-                .to_str()
-                .unwrap();
-            state.config.is_admin(username)
-        }
-        AuthConfig::SSOConfig(_) => false,
-        AuthConfig::SSOAuth(auth_config) => {
-            let auth_config = auth_config.read().await;
-            if let Some(token) = jar.get(auth_config.get_cookie_name()) {
-                let bucket = jar.get(BUCKET_NAME).map(|x| x.value());
-                auth_config.can_delete(&state.config, token.value(), bucket)
-            } else {
-                false
-            }
-        }
+/// Parts uploaded so far, so the client knows where to resume
+async fn session_status(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let user = request_user(&state, &headers, &jar).await;
+    match state.sessions.status(&id, &s3.config.alias, &user).await {
+        Some(status) => (StatusCode::OK, Json(status)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Upload session not found").into_response(),
     }
 }
 
-async fn bool_check_can_upload(state: Arc<AppState>, headers: HeaderMap, jar: CookieJar) -> bool {
-    match state.config.get_auth_config() {
-        AuthConfig::None => true,
-        AuthConfig::Header(_) => {
-            let username = headers
-                .get(state.config.get_header_auth_header())
-                .unwrap()
-                .to_str()
-                .unwrap();
-            state.config.is_admin(username)
+/// Uploads part `part` (1 to 10000), all parts but the last must be 5 MiB or bigger
+async fn session_part(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path((id, part)): Path<(String, u32)>,
+    headers: HeaderMap,
+    mut body: BodyStream,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let user = request_user(&state, &headers, &jar).await;
+    let expected = match Checksums::expected(&headers) {
+        Ok(expected) => expected,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    if part == 0 || part > MAX_PART_NUMBER {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Part number must be from 1 to 10000",
+        )
+            .into_response();
+    }
+    let Some((client, path, upload_id)) = state.sessions.target(&id, &s3.config.alias, &user).await
+    else {
+        return (StatusCode::NOT_FOUND, "Upload session not found").into_response();
+    };
+    let limit = state.config().await.s3.upload_memory_pool;
+    let size = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(limit);
+    if size > limit {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Part is bigger than {limit} bytes"),
+        )
+            .into_response();
+    }
+    let _memory = match MEMORY.acquire(size).await {
+        Ok(memory) => memory,
+        Err(e) => return transfer_error(e),
+    };
+    let mut data = Vec::new();
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(chunk) => data.extend_from_slice(&chunk),
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
         }
-        AuthConfig::SSOConfig(_) => false,
-        AuthConfig::SSOAuth(auth_config) => {
-            let auth_config = auth_config.read().await;
-            if let Some(token) = jar.get(auth_config.get_cookie_name()) {
-                let bucket = jar.get(BUCKET_NAME).map(|x| x.value());
-                auth_config.can_upload(&state.config, token.value(), bucket)
-            } else {
-                false
-            }
+        if data.len() > size {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Part is bigger than {size} bytes"),
+            )
+                .into_response();
         }
     }
+    let size = data.len() as u64;
+    let mut hasher = Hasher::new();
+    hasher.update(&data);
+    let crc = hasher.crc32c();
+    let checksums = hasher.finish();
+    if let Err(e) = client.verify("upload", &checksums, &expected) {
+        return transfer_error(e);
+    }
+    let etag = match client.upload_part(&path, &upload_id, part, data).await {
+        Ok(etag) => etag,
+        Err(e) => return transfer_error(e),
+    };
+    let part = SessionPart {
+        part_number: part,
+        size,
+        etag,
+        sha256: checksums.sha256.unwrap_or_default(),
+        crc32c: checksums.crc32c.unwrap_or_default(),
+        crc,
+    };
+    match state
+        .sessions
+        .add_part(&id, &s3.config.alias, &user, part)
+        .await
+    {
+        Some(status) => (StatusCode::OK, Json(status)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Upload session not found").into_response(),
+    }
 }
 
-async fn check_can_delete(
+/// `x-checksum-crc32c` or `Digest: crc32c=...` of the whole file is checked before completion
+async fn session_complete(
     state: State<Arc<AppState>>,
-    headers: HeaderMap,
     jar: CookieJar,
+    Path(id): Path<String>,
+    headers: HeaderMap,
 ) -> Response {
-    Json(bool_check_can_delete(state.0, headers, jar).await).into_response()
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let expected = match Checksums::expected(&headers) {
+        Ok(expected) => expected,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let user = request_user(&state, &headers, &jar).await;
+    let Some(session) = state.sessions.take(&id, &s3.config.alias, &user).await else {
+        return (StatusCode::NOT_FOUND, "Upload session not found").into_response();
+    };
+    let checksums = session.checksums();
+    if let Err(e) = session.client.verify("upload", &checksums, &expected) {
+        state.sessions.restore(&id, session).await;
+        return transfer_error(e);
+    }
+    // the file may have appeared since the session started
+    let target = complete_target(&state, &s3, &session.path, session.policy, &headers, &jar);
+    let create_only = match target.await {
+        Ok(create_only) => create_only,
+        Err(response) => {
+            state.sessions.restore(&id, session).await;
+            return response;
+        }
+    };
+    let result = session
+        .client
+        .complete_upload(
+            &session.path,
+            &session.upload_id,
+            session.parts(),
+            Some(session.size()),
+            create_only,
+        )
+        .await;
+    match result {
+        Ok(_) => {
+            session
+                .client
+                .store_checksums(&session.path, &checksums)
+                .await;
+            (digest_headers(&checksums), "OK").into_response()
+        }
+        Err(e) => {
+            // e.g. a part is missing, the client may upload it and try again
+            state.sessions.restore(&id, session).await;
+            transfer_error(e)
+        }
+    }
 }
 
-async fn check_can_upload(
+async fn session_abort(
     state: State<Arc<AppState>>,
-    headers: HeaderMap,
     jar: CookieJar,
+    Path(id): Path<String>,
+    headers: HeaderMap,
 ) -> Response {
-    Json(bool_check_can_upload(state.0, headers, jar).await).into_response()
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let user = request_user(&state, &headers, &jar).await;
+    let Some(session) = state.sessions.take(&id, &s3.config.alias, &user).await else {
+        return (StatusCode::NOT_FOUND, "Upload session not found").into_response();
+    };
+    match session
+        .client
+        .abort_upload(&session.path, &session.upload_id)
+        .await
+    {
+        Ok(_) => (StatusCode::OK, "OK").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
-async fn can_delete<B>(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
-    jar: CookieJar,
+/// 503 if the transfer did not get memory, 400 if checksums do not match, 409 if a `create_only`
+/// upload found the file, 500 otherwise
+fn transfer_error(e: anyhow::Error) -> Response {
+    if e.is::<MemoryExhausted>() {
+        return (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response();
+    }
+    if e.is::<ChecksumMismatch>() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    if e.is::<ObjectExists>() {
+        return (StatusCode::CONFLICT, e.to_string()).into_response();
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+}
+
+/// RFC 3230 `Digest` and `x-checksum-*` headers
+fn digest_headers(checksums: &Checksums) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in [
+        ("digest", checksums.digest_header()),
+        (SHA256_HEADER, checksums.sha256.clone()),
+        (CRC32C_HEADER, checksums.crc32c.clone()),
+    ] {
+        if let Some(value) = value.and_then(|x| HeaderValue::from_str(&x).ok()) {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
+
+#[derive(Serialize)]
+struct ChecksumResponse {
+    #[serde(flatten)]
+    checksums: Checksums,
+    /// false if the checksums were stored at upload
+    computed: bool,
+}
+
+/// Stored checksums of the file, computed and stored if there are none
+async fn checksum(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(path): Path<String>,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    match s3.checksums(&path).await {
+        Ok((checksums, computed)) => (
+            StatusCode::OK,
+            digest_headers(&checksums),
+            Json(ChecksumResponse {
+                checksums,
+                computed,
+            }),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    }
+}
+
+async fn mkdir(state: State<Arc<AppState>>, jar: CookieJar, Path(path): Path<String>) -> Response {
+    let s3 = match state.get_s3_from_jar(jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    match s3.mkdir(&path).await {
+        Ok(_) => (StatusCode::OK, "OK").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct VersionQuery {
+    #[serde(rename = "versionId")]
+    version_id: Option<String>,
+}
+
+async fn download(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    Query(version): Query<VersionQuery>,
+) -> Response {
+    let config = state.config().await;
+    let s3 = match state.get_s3_from_jar(jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let prepared = match &version.version_id {
+        None => s3.prepare_download(&path).await,
+        Some(version_id) => s3.prepare_version_download(&path, version_id).await,
+    };
+    match prepared {
+        Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+        Ok(prepared) => serve_download(&config, s3, path, version.version_id, prepared).await,
+    }
+}
+
+/// Streams the file, `prepared` is its name, size and mime type
+async fn serve_download(
+    config: &Config,
+    s3: Arc<S3Client>,
+    path: String,
+    version_id: Option<String>,
+    (file_name, file_size, file_mime): (String, u64, String),
+) -> Response {
+    let memory = match MEMORY.acquire(config.s3.download_memory_pool).await {
+        Ok(memory) => memory,
+        Err(e) => return transfer_error(e),
+    };
+    // tags, and the checksums in them, are of the current version
+    let stored = match version_id {
+        None => s3.stored_checksums(&path).await,
+        Some(_) => Checksums::default(),
+    };
+    let digest = digest_headers(&stored);
+    let (tx, rx) = duplex(config.s3.download_memory_pool);
+    let guard = DownloadGuard::new(config.s3.download_memory_pool);
+    spawn(
+        async move {
+            let _guard = guard;
+            let _memory = memory;
+            if let Err(e) = s3.download(&path, version_id.as_deref(), tx, &stored).await {
+                warn!("Error while downloading file: {}", e);
+            }
+        }
+        .in_current_span(),
+    );
+    let body = AsyncReadBody::new(rx);
+    let headers = [
+        (CONTENT_TYPE, file_mime),
+        (
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", file_name),
+        ),
+        (CONTENT_LENGTH, file_size.to_string()),
+    ];
+    (headers, digest, body).into_response()
+}
+
+#[derive(Deserialize)]
+struct PresignQuery {
+    /// seconds, `presign_max_expiry` of the bucket by default
+    expires: Option<u64>,
+    #[serde(default)]
+    method: PresignMethod,
+    overwrite: Option<String>,
+}
+
+/// URL to GET the file straight from S3, or to PUT it with upload permission
+async fn presign(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    Query(query): Query<PresignQuery>,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let max_expiry = s3.config.presign_max_expiry.min(MAX_PRESIGN_EXPIRY);
+    if max_expiry == 0 {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            "Presigned URLs are not enabled for the bucket",
+        )
+            .into_response();
+    }
+    let expiry = query.expires.unwrap_or(max_expiry);
+    if expiry == 0 || expiry > max_expiry {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("expires must be from 1 to {max_expiry} seconds"),
+        )
+            .into_response();
+    }
+    let path = match query.method {
+        PresignMethod::Get => match s3.exists(&path).await {
+            Ok(true) => path,
+            Ok(false) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        },
+        PresignMethod::Put => {
+            if !bool_check_can_upload(state.clone(), headers.clone(), jar.clone()).await {
+                return (StatusCode::FORBIDDEN, "403 access denied").into_response();
+            }
+            let overwrite = OverwriteQuery {
+                overwrite: query.overwrite,
+            };
+            match upload_target(&state, &s3, path, &overwrite, &headers, &jar).await {
+                Ok(path) => path,
+                Err(response) => return response,
+            }
+        }
+    };
+    match s3.presign(&path, query.method, expiry as u32).await {
+        Ok(presigned) => (StatusCode::OK, Json(presigned)).into_response(),
+        Err(e) if e.is::<NoCredentials>() => {
+            (StatusCode::NOT_IMPLEMENTED, e.to_string()).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn versions_root(state: State<Arc<AppState>>, jar: CookieJar) -> Response {
+    versions(state, jar, Path(String::new())).await
+}
+
+/// Versions of a file, or of the files in a folder when the path ends with `/`
+async fn versions(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(path): Path<String>,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    match s3.versions(&path).await {
+        Ok(versions) => (StatusCode::OK, Json(versions)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RestoreVersionRequest {
+    version_id: String,
+}
+
+/// Makes an old version current. Upload permission is checked by the layer,
+/// replacing an existing file needs delete permission as overwriting does
+async fn restore_version(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<RestoreVersionRequest>,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    match s3.exists(&path).await {
+        Ok(false) => {}
+        Ok(true) => {
+            if !bool_check_can_delete(state.0.clone(), headers, jar).await {
+                return (
+                    StatusCode::FORBIDDEN,
+                    "Overwriting a file needs delete permission",
+                )
+                    .into_response();
+            }
+        }
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+    let size = match s3
+        .prepare_version_download(&path, &request.version_id)
+        .await
+    {
+        Ok((_, size, _)) => size,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    match s3.restore_version(&path, &request.version_id, size).await {
+        Ok(_) => (StatusCode::OK, "OK").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn del(state: State<Arc<AppState>>, jar: CookieJar, Path(path): Path<String>) -> Response {
+    let s3 = match state.get_s3_from_jar(jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let result = match s3.config.trash {
+        Some(_) => s3.trash(&path, false, None).await.and_then(|report| {
+            match report.failed.into_iter().next() {
+                None => Ok(()),
+                Some(failure) => Err(anyhow!("{}: {}", failure.code, failure.message)),
+            }
+        }),
+        None => s3.delete(&path).await,
+    };
+    match result {
+        Ok(_) => (StatusCode::OK, "OK").into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct DeleteFolderQuery {
+    #[serde(default)]
+    dry_run: bool,
+    /// run as a job, its report is returned at once with 202. `false` waits for the delete
+    background: Option<bool>,
+}
+
+/// Job deleting the folder, or with `background=false` the deletion report, 500 if some keys
+/// are left. `dry_run` returns what would be deleted
+async fn delete_folder(
+    state: State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    Query(query): Query<DeleteFolderQuery>,
+) -> Response {
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    if query.dry_run {
+        return match s3.delete_preview(&path).await {
+            Ok(preview) => (StatusCode::OK, Json(preview)).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+    }
+    if query.background.unwrap_or(true) {
+        let user = request_user(&state, &headers, &jar).await;
+        let bucket = s3.config.alias.clone();
+        let job = state
+            .jobs
+            .spawn(
+                "delete_folder",
+                &user,
+                &bucket,
+                &path.clone(),
+                |job| async move {
+                    let report = match s3.config.trash {
+                        Some(_) => s3.trash(&path, true, Some(&job)).await?,
+                        None => s3.delete_prefix(&path, Some(&job)).await?,
+                    };
+                    let result = serde_json::to_value(&report)?;
+                    if !report.failed.is_empty() {
+                        return Err(JobFailed {
+                            message: format!("{} files are not deleted", report.failed.len()),
+                            result,
+                        }
+                        .into());
+                    }
+                    Ok(result)
+                },
+            )
+            .await;
+        return (StatusCode::ACCEPTED, Json(job.report())).into_response();
+    }
+    let result = match s3.config.trash {
+        Some(_) => s3.trash(&path, true, None).await,
+        None => s3.delete_prefix(&path, None).await,
+    };
+    match result {
+        Ok(report) if report.failed.is_empty() => (StatusCode::OK, Json(report)).into_response(),
+        Ok(report) => (StatusCode::INTERNAL_SERVER_ERROR, Json(report)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn job_list(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Response {
+    let user = request_user(&state, &headers, &jar).await;
+    (StatusCode::OK, Json(state.jobs.list(&user).await)).into_response()
+}
+
+async fn job_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let user = request_user(&state, &headers, &jar).await;
+    match state.jobs.get(&id, &user).await {
+        Some(job) => (StatusCode::OK, Json(job.report())).into_response(),
+        None => (StatusCode::NOT_FOUND, "Job not found").into_response(),
+    }
+}
+
+/// The job stops after its current step, the report tells when it does
+async fn job_cancel(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let user = request_user(&state, &headers, &jar).await;
+    match state.jobs.get(&id, &user).await {
+        Some(job) => {
+            job.cancel();
+            (StatusCode::OK, Json(job.report())).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Job not found").into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ShareRequest {
+    /// seconds, a day or `share_max_ttl` if it's shorter by default
+    expires_in: Option<u64>,
+    password: Option<String>,
+    max_downloads: Option<u64>,
+}
+
+/// Public link to a file, anyone who can view the file may create one
+async fn share_create(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    Json(request): Json<ShareRequest>,
+) -> Response {
+    let config = state.config().await;
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let max_ttl = config.s3.share_max_ttl.min(MAX_SHARE_TTL);
+    let ttl = request.expires_in.unwrap_or(max_ttl.min(86400));
+    if ttl == 0 || ttl > max_ttl {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("expires_in must be from 1 to {max_ttl} seconds"),
+        )
+            .into_response();
+    }
+    if request.max_downloads == Some(0) {
+        return (StatusCode::BAD_REQUEST, "max_downloads must be positive").into_response();
+    }
+    match s3.exists(&path).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+    let user = request_user(&state, &headers, &jar).await;
+    let password = request.password.as_deref().filter(|x| !x.is_empty());
+    let share = state
+        .shares
+        .create(
+            &s3.config.alias,
+            &path,
+            &user,
+            ttl,
+            password,
+            request.max_downloads,
+        )
+        .await;
+    match share {
+        Ok(share) => (StatusCode::OK, Json(share)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Admins see every link, others their own ones
+async fn share_list(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Response {
+    let user = request_user(&state, &headers, &jar).await;
+    let admin = bool_check_can_delete(state.clone(), headers, jar).await;
+    let user = (!admin).then_some(user.as_str());
+    (StatusCode::OK, Json(state.shares.list(user).await)).into_response()
+}
+
+async fn share_revoke(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(token): Path<String>,
+) -> Response {
+    let user = request_user(&state, &headers, &jar).await;
+    let admin = bool_check_can_delete(state.clone(), headers, jar).await;
+    let user = (!admin).then_some(user.as_str());
+    match state.shares.revoke(&token, user).await {
+        true => (StatusCode::OK, "OK").into_response(),
+        false => (StatusCode::NOT_FOUND, "Share link not found").into_response(),
+    }
+}
+
+const SHARE_PASSWORD_FORM: &str = r#"<!DOCTYPE html>
+<html><head><title>s3clix</title></head><body>
+<form method="post"><p>This file is protected with password</p>
+<input type="password" name="password" autofocus> <button type="submit">Download</button>
+</form></body></html>"#;
+
+#[derive(Deserialize)]
+struct ShareUnlock {
+    password: String,
+}
+
+async fn share_download(
+    state: State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> Response {
+    let client = client_address(&state, &headers, peer).await;
+    serve_share(&state, &token, None, client).await
+}
+
+/// Password form of a protected link is posted here
+async fn share_unlock(
+    state: State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    Form(form): Form<ShareUnlock>,
+) -> Response {
+    let client = client_address(&state, &headers, peer).await;
+    serve_share(&state, &token, Some(&form.password), client).await
+}
+
+/// Address of the client: the last one the reverse proxy added to `client_ip_header`
+/// when it is configured, the connected peer otherwise
+async fn client_address(state: &AppState, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+    let config = state.config().await;
+    config
+        .get_client_ip_header()
+        .and_then(|x| headers.get_all(x).iter().next_back())
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.rsplit(',').next())
+        .and_then(|x| x.trim().parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+fn share_error(e: anyhow::Error) -> Response {
+    if e.is::<SharePassword>() {
+        return (StatusCode::UNAUTHORIZED, Html(SHARE_PASSWORD_FORM)).into_response();
+    }
+    if e.is::<ShareGone>() {
+        return (StatusCode::GONE, e.to_string()).into_response();
+    }
+    if let Some(ShareThrottled(wait)) = e.downcast_ref() {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(RETRY_AFTER, wait.to_string())],
+            e.to_string(),
+        )
+            .into_response();
+    }
+    if e.is::<ShareNotFound>() {
+        return (StatusCode::NOT_FOUND, e.to_string()).into_response();
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+}
+
+async fn serve_share(
+    state: &AppState,
+    token: &str,
+    password: Option<&str>,
+    client: IpAddr,
+) -> Response {
+    let share = match state.shares.get(token, password, client).await {
+        Ok(share) => share,
+        Err(e) => return share_error(e),
+    };
+    let s3 = match state.get_available_s3(Some(&share.bucket)).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let prepared = match s3.prepare_download(&share.path).await {
+        Ok(prepared) => prepared,
+        Err(e) => return (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    if let Err(e) = share.count_download() {
+        return (StatusCode::GONE, e.to_string()).into_response();
+    }
+    info!(
+        "Share link of {} downloads {} from {}",
+        share.user, share.path, share.bucket
+    );
+    let config = state.config().await;
+    serve_download(&config, s3, share.path.clone(), None, prepared).await
+}
+
+#[derive(Deserialize)]
+struct FileRequestCreate {
+    /// seconds, a day or `share_max_ttl` if it's shorter by default
+    expires_in: Option<u64>,
+    max_file_size: Option<u64>,
+    max_total_size: Option<u64>,
+}
+
+/// Upload-only link to the folder, upload permission is checked by the layer
+async fn file_request_create(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(path): Path<String>,
+    Json(request): Json<FileRequestCreate>,
+) -> Response {
+    let config = state.config().await;
+    let s3 = match state.get_s3_from_jar(jar.clone()).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let max_ttl = config.s3.share_max_ttl.min(MAX_SHARE_TTL);
+    let ttl = request.expires_in.unwrap_or(max_ttl.min(86400));
+    if ttl == 0 || ttl > max_ttl {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("expires_in must be from 1 to {max_ttl} seconds"),
+        )
+            .into_response();
+    }
+    let max_size = config.s3.file_request_max_size;
+    let max_total_size = request.max_total_size.unwrap_or(max_size);
+    if request.max_file_size == Some(0) || max_total_size == 0 || max_total_size > max_size {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Size limits must be from 1 to {max_size} bytes"),
+        )
+            .into_response();
+    }
+    let prefix = match path.trim_matches('/') {
+        "" => String::new(),
+        path => format!("{path}/"),
+    };
+    let user = request_user(&state, &headers, &jar).await;
+    let request = state
+        .shares
+        .create_request(
+            &s3.config.alias,
+            &prefix,
+            &user,
+            ttl,
+            request.max_file_size,
+            max_total_size,
+        )
+        .await;
+    (StatusCode::OK, Json(request)).into_response()
+}
+
+/// Admins see every file request, others their own ones
+async fn file_request_list(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Response {
+    let user = request_user(&state, &headers, &jar).await;
+    let admin = bool_check_can_delete(state.clone(), headers, jar).await;
+    let user = (!admin).then_some(user.as_str());
+    (StatusCode::OK, Json(state.shares.list_requests(user).await)).into_response()
+}
+
+async fn file_request_revoke(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Path(token): Path<String>,
+) -> Response {
+    let user = request_user(&state, &headers, &jar).await;
+    let admin = bool_check_can_delete(state.clone(), headers, jar).await;
+    let user = (!admin).then_some(user.as_str());
+    match state.shares.revoke_request(&token, user).await {
+        true => (StatusCode::OK, "OK").into_response(),
+        false => (StatusCode::NOT_FOUND, "File request not found").into_response(),
+    }
+}
+
+/// Uploads every chosen file with `PUT /r/{token}/{name}`
+const FILE_REQUEST_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><title>s3clix</title></head><body>
+<p>Choose files to send</p>
+<input type="file" id="files" multiple> <button id="send">Send</button>
+<ul id="log"></ul>
+<script>
+document.getElementById('send').onclick = async () => {
+  const log = document.getElementById('log');
+  for (const file of document.getElementById('files').files) {
+    const item = document.createElement('li');
+    item.textContent = file.name + ': sending';
+    log.appendChild(item);
+    const url = location.pathname.replace(/\/$/, '') + '/' + encodeURIComponent(file.name);
+    const response = await fetch(url, {method: 'PUT', body: file});
+    item.textContent = file.name + ': ' + (response.ok ? 'sent' : await response.text());
+  }
+};
+</script>
+</body></html>"#;
+
+async fn file_request_page(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> Response {
+    match state.shares.get_request(&token).await {
+        Ok(_) => (StatusCode::OK, Html(FILE_REQUEST_PAGE)).into_response(),
+        Err(e) => share_error(e),
+    }
+}
+
+/// Anonymous upload into the folder of a file request. Content-Length is required to check
+/// the limits and caps the body. A file created meanwhile under the same name is kept, 409
+async fn file_request_upload(
+    State(state): State<Arc<AppState>>,
+    Path((token, name)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> Response {
+    let request = match state.shares.get_request(&token).await {
+        Ok(request) => request,
+        Err(e) => return share_error(e),
+    };
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return (StatusCode::BAD_REQUEST, "Invalid file name").into_response();
+    }
+    let Some(size) = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok())
+    else {
+        return (StatusCode::LENGTH_REQUIRED, "Content-Length is required").into_response();
+    };
+    let expected = match Checksums::expected(&headers) {
+        Ok(expected) => expected,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let s3 = match state.get_available_s3(Some(&request.bucket)).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    let path = format!("{}{name}", request.prefix);
+    let jar = CookieJar::new();
+    let path = match policy_target(&state, &s3, path, OverwritePolicy::Rename, &headers, &jar).await
+    {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if let Err(e) = request.reserve(size) {
+        return (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response();
+    }
+    let config = state.config().await;
+    let res = match config.s3.upload_type {
+        S3UploadType::Parallel => {
+            s3.upload_parallel(&path, capped(body, size), &expected, true)
+                .await
+        }
+        S3UploadType::Serial => {
+            s3.upload_serial(&path, capped(body, size), &expected, true)
+                .await
+        }
+    };
+    match res {
+        Ok(checksums) => {
+            request.uploaded();
+            info!(
+                "File request {} of {} received {path} in {}, {size} bytes",
+                request.token, request.user, request.bucket
+            );
+            uploaded(&checksums, &path)
+        }
+        Err(e) => {
+            request.release(size);
+            warn!(
+                "File request {} of {} failed to receive {path}: {e}",
+                request.token, request.user
+            );
+            if e.chain().any(|x| x.is::<RequestLimit>()) {
+                return (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()).into_response();
+            }
+            transfer_error(e)
+        }
+    }
+}
+
+/// Fails the body once it is longer than `size`, whatever the transport does with Content-Length
+fn capped(body: BodyStream, size: u64) -> impl Stream<Item = Result<Bytes, axum::Error>> + Unpin {
+    let mut received = 0u64;
+    body.map(move |chunk| {
+        let chunk = chunk?;
+        received += chunk.len() as u64;
+        match received > size {
+            true => Err(axum::Error::new(RequestLimit(format!(
+                "Body is longer than Content-Length of {size} bytes"
+            )))),
+            false => Ok(chunk),
+        }
+    })
+}
+
+/// Trash of the bucket, 501 if it isn't enabled
+async fn trash_client(state: &AppState, jar: CookieJar) -> Result<Arc<S3Client>, Response> {
+    let s3 = state.get_s3_from_jar(jar).await?;
+    match s3.config.trash {
+        Some(_) => Ok(s3),
+        None => Err((
+            StatusCode::NOT_IMPLEMENTED,
+            "Trash is not enabled for the bucket",
+        )
+            .into_response()),
+    }
+}
+
+async fn trash_list(state: State<Arc<AppState>>, jar: CookieJar) -> Response {
+    let s3 = match trash_client(&state, jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    match s3.trash_entries().await {
+        Ok(entries) => (StatusCode::OK, Json(entries)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// `.trash/` is listed, restored and purged with the `/trash` routes only: every route taking
+/// a `*path` refuses paths in it, so trash entries can't be read around them or forged
+async fn reject_trash_paths<B>(
+    params: Option<Path<HashMap<String, String>>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if params.is_some_and(|Path(x)| x.get("path").is_some_and(|x| in_trash(x))) {
+        return trash_path();
+    }
+    next.run(request).await
+}
+
+fn trash_path() -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        "Files in trash are managed with /api/trash",
+    )
+        .into_response()
+}
+
+/// 404 for unknown entries, 409 for conflicts, 500 otherwise
+fn trash_error(e: anyhow::Error) -> Response {
+    if e.is::<TrashNotFound>() {
+        return (StatusCode::NOT_FOUND, e.to_string()).into_response();
+    }
+    if e.is::<TrashConflict>() {
+        return (StatusCode::CONFLICT, e.to_string()).into_response();
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+}
+
+/// Restored paths, 409 if any of them exists
+async fn trash_restore(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let s3 = match trash_client(&state, jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    match s3.restore_trash(&id).await {
+        Ok(paths) => (StatusCode::OK, Json(paths)).into_response(),
+        Err(e) => trash_error(e),
+    }
+}
+
+async fn trash_purge(
+    state: State<Arc<AppState>>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let s3 = match trash_client(&state, jar).await {
+        Ok(s3) => s3,
+        Err(response) => return response,
+    };
+    match s3.purge_trash(&id).await {
+        Ok(_) => (StatusCode::OK, "OK").into_response(),
+        Err(e) => trash_error(e),
+    }
+}
+
+// AUTH
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Permission of a request made with API token, `None` if the request has no token
+async fn token_can(
+    config: &Config,
+    headers: &HeaderMap,
+    jar: &CookieJar,
+    action: Action,
+) -> Option<bool> {
+    let token = bearer(headers)?;
+    let Some(token) = config.api_token(token) else {
+        warn!("Request with unknown API token");
+        return Some(false);
+    };
+    let bucket = jar.get(BUCKET_NAME).map(|x| x.value());
+    Some(match config.get_auth_config() {
+        AuthConfig::None => true,
+        AuthConfig::Header(_) => matches!(action, Action::View) || config.is_admin(&token.name),
+        AuthConfig::SSOConfig(_) => false,
+        AuthConfig::SSOAuth(auth_config) => {
+            auth_config
+                .read()
+                .await
+                .groups_can(action, config, &token.groups, bucket)
+        }
+    })
+}
+
+/// Who makes the request: API token name, header user or SSO username.
+/// Empty without authentication
+async fn request_user(state: &AppState, headers: &HeaderMap, jar: &CookieJar) -> String {
+    let config = state.config().await;
+    if let Some(token) = bearer(headers).and_then(|x| config.api_token(x)) {
+        return token.name.clone();
+    }
+    match config.get_auth_config() {
+        AuthConfig::None | AuthConfig::SSOConfig(_) => String::new(),
+        AuthConfig::Header(_) => headers
+            .get(config.get_header_auth_header())
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or_default()
+            .to_owned(),
+        AuthConfig::SSOAuth(auth_config) => {
+            let auth_config = auth_config.read().await;
+            jar.get(auth_config.get_cookie_name())
+                .and_then(|x| auth_config.username(x.value()))
+                .unwrap_or_default()
+        }
+    }
+}
+
+async fn bool_check_can_delete(state: Arc<AppState>, headers: HeaderMap, jar: CookieJar) -> bool {
+    let config = state.config().await;
+    if let Some(allowed) = token_can(&config, &headers, &jar, Action::Delete).await {
+        return allowed;
+    }
+    match config.get_auth_config() {
+        AuthConfig::None => true,
+        AuthConfig::Header(_) => {
+            let username = headers
+                .get(config.get_header_auth_header())
+                .unwrap()
+                .to_str()
+                .unwrap();
+            config.is_admin(username)
+        }
+        AuthConfig::SSOConfig(_) => false,
+        AuthConfig::SSOAuth(auth_config) => {
+            let auth_config = auth_config.read().await;
+            if let Some(token) = jar.get(auth_config.get_cookie_name()) {
+                let bucket = jar.get(BUCKET_NAME).map(|x| x.value());
+                auth_config.can_delete(&config, token.value(), bucket)
+            } else {
+                false
+            }
+        }
+    }
+}
+
+async fn bool_check_can_upload(state: Arc<AppState>, headers: HeaderMap, jar: CookieJar) -> bool {
+    let config = state.config().await;
+    if let Some(allowed) = token_can(&config, &headers, &jar, Action::Upload).await {
+        return allowed;
+    }
+    match config.get_auth_config() {
+        AuthConfig::None => true,
+        AuthConfig::Header(_) => {
+            let username = headers
+                .get(config.get_header_auth_header())
+                .unwrap()
+                .to_str()
+                .unwrap();
+            config.is_admin(username)
+        }
+        AuthConfig::SSOConfig(_) => false,
+        AuthConfig::SSOAuth(auth_config) => {
+            let auth_config = auth_config.read().await;
+            if let Some(token) = jar.get(auth_config.get_cookie_name()) {
+                let bucket = jar.get(BUCKET_NAME).map(|x| x.value());
+                auth_config.can_upload(&config, token.value(), bucket)
+            } else {
+                false
+            }
+        }
+    }
+}
+
+async fn check_can_delete(
+    state: State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Response {
+    Json(bool_check_can_delete(state.0, headers, jar).await).into_response()
+}
+
+async fn check_can_upload(
+    state: State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Response {
+    Json(bool_check_can_upload(state.0, headers, jar).await).into_response()
+}
+
+#[instrument(level = "trace", skip_all)]
+async fn can_delete<B>(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    jar: CookieJar,
     req: Request<B>,
     next: Next<B>,
 ) -> Response {
@@ -468,6 +2054,7 @@ async fn can_delete<B>(
     }
 }
 
+#[instrument(level = "trace", skip_all)]
 async fn can_upload<B>(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -482,8 +2069,9 @@ async fn can_upload<B>(
     }
 }
 
-const RETURN_COOKIE: &'static str = "return_to";
+const RETURN_COOKIE: &str = "return_to";
 
+#[instrument(level = "trace", skip_all)]
 async fn auth_middleware<B>(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -491,10 +2079,16 @@ async fn auth_middleware<B>(
     req: Request<B>,
     next: Next<B>,
 ) -> Response {
-    match state.config.get_auth_config() {
+    let config = state.config().await;
+    match token_can(&config, &headers, &jar, Action::View).await {
+        Some(true) => return next.run(req).await,
+        Some(false) => return (StatusCode::FORBIDDEN, "403 access denied").into_response(),
+        None => {}
+    }
+    match config.get_auth_config() {
         AuthConfig::None => {}
         AuthConfig::Header(_) => {
-            let username = match headers.get(state.config.get_header_auth_header()) {
+            let username = match headers.get(config.get_header_auth_header()) {
                 Some(s) => s.to_str().unwrap_or(""),
                 None => "",
             };
@@ -511,7 +2105,7 @@ async fn auth_middleware<B>(
             let cookie_name = auth_config.get_cookie_name().to_owned();
             if let Some(cookie) = jar.get(&cookie_name) {
                 let bucket = jar.get(BUCKET_NAME).map(|x| x.value());
-                has_token = auth_config.can_view(&state.config, cookie.value(), bucket);
+                has_token = auth_config.can_view(&config, cookie.value(), bucket);
             }
             if !has_token {
                 let url = auth_config.build_redirect_url();
@@ -532,3 +2126,59 @@ async fn auth_middleware<B>(
     }
     next.run(req).await
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+web:
+  path: web
+  port: 8080
+auth: None
+s3:
+  upload_type: Parallel
+  workers: 1
+  upload_memory_pool: 10000000
+  download_memory_pool: 10000000
+  buckets: []
+"#;
+
+    #[tokio::test]
+    async fn test_trash_paths_rejected() {
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let state = Arc::new(AppState {
+            current: RwLock::new(Arc::new(Snapshot {
+                config: Arc::new(config),
+                s3: Vec::new(),
+            })),
+            health: Arc::new(Health::new()),
+            sessions: UploadSessions::new(60),
+            jobs: Jobs::new(),
+            shares: Shares::new(),
+        });
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(api_router(state).into_make_service());
+        spawn(server);
+        let client = reqwest::Client::new();
+        let url = |route: &str| format!("http://{address}{route}");
+        for request in [
+            client
+                .put(url("/upload/.trash/20250101-000000-00000000/a.txt"))
+                .body("x"),
+            client.get(url("/presign/.trash/20250101-000000-00000000/a.txt")),
+            client.get(url("/presign/.trash?method=put")),
+            client.post(url("/mkdir/.trash/x")),
+            client.post(url("/share/%2Etrash/x")).json(&()),
+        ] {
+            let response = request.send().await.unwrap();
+            assert_eq!(response.status().as_u16(), 403);
+        }
+        // a folder named .trash below the root is an ordinary folder
+        let response = client.get(url("/presign/a/.trash/x")).send().await.unwrap();
+        assert_ne!(response.status().as_u16(), 403);
+    }
+}
@@ -0,0 +1,551 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use s3::serde_types::{ListBucketResult, Object};
+use serde::{Deserialize, Serialize};
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::config::SearchIndexConfig;
+use crate::metrics::METRICS;
+use crate::s3::S3Client;
+use crate::trash::TRASH_PREFIX;
+
+/// Keys `visit` copies out of the index at once
+const VISIT_CHUNK: usize = 1000;
+
+/// Modification times as S3 lists them
+pub const MODIFIED_FORMAT: &[FormatItem] =
+    format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z");
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub size: u64,
+    pub last_modified: String,
+}
+
+/// Line of the index file
+#[derive(Serialize, Deserialize)]
+struct IndexLine {
+    key: String,
+    #[serde(flatten)]
+    entry: IndexEntry,
+}
+
+#[derive(Default)]
+struct Keys {
+    keys: BTreeMap<String, IndexEntry>,
+    /// sequence number of the last own write of a key, a rescan started before it keeps the key as is
+    writes: HashMap<String, u64>,
+}
+
+/// Last full listing of a top-level folder
+#[derive(Clone, Copy)]
+struct FolderScan {
+    /// of the first level of the folder as it was listed
+    fingerprint: u64,
+    listed: Instant,
+}
+
+/// Keys of a bucket for search without listing it
+pub struct SearchIndex {
+    keys: RwLock<Keys>,
+    /// top-level folders by prefix, rescans relist the changed and the long unlisted ones only
+    folders: Mutex<HashMap<String, FolderScan>>,
+    /// a crawl or the index file filled it
+    ready: AtomicBool,
+    sequence: AtomicU64,
+    alias: String,
+}
+
+impl SearchIndex {
+    pub fn new(alias: &str) -> Arc<Self> {
+        Arc::new(Self {
+            keys: RwLock::new(Keys::default()),
+            folders: Mutex::new(HashMap::new()),
+            ready: AtomicBool::new(false),
+            sequence: AtomicU64::new(0),
+            alias: alias.to_owned(),
+        })
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.read().unwrap().keys.len()
+    }
+
+    fn update_gauge(&self, len: usize) {
+        METRICS
+            .search_index_keys
+            .with_label_values(&[&self.alias])
+            .set(len as i64);
+    }
+
+    /// Own write: the object is there now
+    pub fn insert(&self, key: &str, size: u64) {
+        let last_modified = OffsetDateTime::now_utc()
+            .format(MODIFIED_FORMAT)
+            .unwrap_or_default();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut keys = self.keys.write().unwrap();
+        keys.keys.insert(
+            key.to_owned(),
+            IndexEntry {
+                size,
+                last_modified,
+            },
+        );
+        keys.writes.insert(key.to_owned(), sequence);
+        self.update_gauge(keys.keys.len());
+    }
+
+    /// Own write: the objects are deleted
+    pub fn remove(&self, removed: &[String]) {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut keys = self.keys.write().unwrap();
+        for key in removed {
+            keys.keys.remove(key);
+            keys.writes.insert(key.clone(), sequence);
+        }
+        self.update_gauge(keys.keys.len());
+    }
+
+    /// Replaces the keys `selected` picks with the listed objects,
+    /// except for the keys written since the listing started at `since`
+    fn replace_where<F>(&self, selected: F, objects: Vec<Object>, since: u64)
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut keys = self.keys.write().unwrap();
+        let Keys { keys, writes } = &mut *keys;
+        let written = |key: &str| writes.get(key).is_some_and(|x| *x > since);
+        keys.retain(|key, _| !selected(key) || written(key));
+        insert_listed(keys, objects, |key| selected(key) && !written(key));
+        self.update_gauge(keys.len());
+    }
+
+    /// `replace_where` for the keys under `prefix`, without going over the whole index
+    fn replace_folder(&self, prefix: &str, objects: Vec<Object>, since: u64) {
+        let mut keys = self.keys.write().unwrap();
+        let Keys { keys, writes } = &mut *keys;
+        let written = |key: &str| writes.get(key).is_some_and(|x| *x > since);
+        let stale: Vec<String> = keys
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .filter(|key| !written(key))
+            .cloned()
+            .collect();
+        for key in stale {
+            keys.remove(&key);
+        }
+        insert_listed(keys, objects, |key| {
+            key.starts_with(prefix) && !written(key)
+        });
+        self.update_gauge(keys.len());
+    }
+
+    /// Calls `f` for the keys under `prefix` in key order until it returns false.
+    /// Keys are copied out `VISIT_CHUNK` at a time, writers wait for a chunk, not the whole scan
+    pub fn visit<F>(&self, prefix: &str, mut f: F)
+    where
+        F: FnMut(&str, &IndexEntry) -> bool,
+    {
+        let mut from = Bound::Included(prefix.to_owned());
+        loop {
+            let chunk: Vec<(String, IndexEntry)> = self
+                .keys
+                .read()
+                .unwrap()
+                .keys
+                .range::<String, _>((from.as_ref(), Bound::Unbounded))
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .take(VISIT_CHUNK)
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect();
+            let Some((last, _)) = chunk.last() else {
+                return;
+            };
+            from = Bound::Excluded(last.clone());
+            let full = chunk.len() == VISIT_CHUNK;
+            for (key, entry) in &chunk {
+                if !f(key, entry) {
+                    return;
+                }
+            }
+            if !full {
+                return;
+            }
+        }
+    }
+
+    /// Fills the index from the file written by `save`
+    fn load(&self, path: &str) -> anyhow::Result<()> {
+        let mut loaded = BTreeMap::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line: IndexLine = serde_json::from_str(&line?)?;
+            loaded.insert(line.key, line.entry);
+        }
+        let mut keys = self.keys.write().unwrap();
+        // own writes made meanwhile are newer than the file
+        let written = std::mem::take(&mut keys.keys);
+        for key in keys.writes.keys() {
+            if !written.contains_key(key) {
+                loaded.remove(key);
+            }
+        }
+        loaded.extend(written);
+        keys.keys = loaded;
+        self.update_gauge(keys.keys.len());
+        self.ready.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Writes the index as JSON lines, into a temporary file renamed over `path`.
+    /// The keys are copied first, so writers don't wait for the disk
+    fn save(&self, path: &str) -> anyhow::Result<()> {
+        let lines: Vec<IndexLine> = self
+            .keys
+            .read()
+            .unwrap()
+            .keys
+            .iter()
+            .map(|(key, entry)| IndexLine {
+                key: key.clone(),
+                entry: entry.clone(),
+            })
+            .collect();
+        let temporary = format!("{path}.tmp");
+        let mut file = BufWriter::new(File::create(&temporary)?);
+        for line in &lines {
+            serde_json::to_writer(&mut file, line)?;
+            file.write_all(b"\n")?;
+        }
+        file.flush()?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+fn insert_listed<F>(keys: &mut BTreeMap<String, IndexEntry>, objects: Vec<Object>, accepted: F)
+where
+    F: Fn(&str) -> bool,
+{
+    for object in objects {
+        if accepted(&object.key) {
+            keys.insert(
+                object.key,
+                IndexEntry {
+                    size: object.size,
+                    last_modified: object.last_modified,
+                },
+            );
+        }
+    }
+}
+
+/// Index files are read and written off the async runtime
+async fn blocking<F>(f: F) -> anyhow::Result<()>
+where
+    F: FnOnce() -> anyhow::Result<()> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// Changes when a file of the page is added, removed or replaced, or a subfolder comes or goes
+fn fingerprint(page: &ListBucketResult) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for object in &page.contents {
+        (
+            &object.key,
+            object.size,
+            &object.last_modified,
+            &object.e_tag,
+        )
+            .hash(&mut hasher);
+    }
+    for folder in page.common_prefixes.iter().flatten() {
+        folder.prefix.hash(&mut hasher);
+    }
+    page.is_truncated.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// `folder/` of `folder/file`, empty for keys in the bucket root
+fn top_folder(key: &str) -> &str {
+    key.find('/').map(|x| &key[..=x]).unwrap_or_default()
+}
+
+impl S3Client {
+    /// Records an own upload or copy of `size` bytes in the index
+    pub fn index_put(&self, path: &str, size: u64) {
+        let Some(index) = &self.index else {
+            return;
+        };
+        if !path.starts_with(TRASH_PREFIX) {
+            index.insert(path, size);
+        }
+    }
+
+    /// `index_put` of an upload whose parts went past s3clix, the size is asked from S3
+    pub async fn index_head(&self, path: &str) {
+        if self.index.is_none() || path.starts_with(TRASH_PREFIX) {
+            return;
+        }
+        match self.size(path).await {
+            Ok(size) => self.index_put(path, size),
+            Err(e) => warn!("Cannot index {path}: {e}"),
+        }
+    }
+
+    /// Records own deletes in the index
+    pub fn index_remove(&self, keys: &[String]) {
+        if let Some(index) = &self.index {
+            index.remove(keys);
+        }
+    }
+
+    /// Relists the bucket root and the top-level folders which changed, replacing that part of
+    /// the index, so the index keeps answering during the rescan. S3 can't tell what changed
+    /// under a prefix, so a folder counts as changed when the first level of it, one cheap
+    /// listing, differs from its last full listing. Changes deeper in a folder which looks the
+    /// same are picked up once it is older than `full_rescan_interval`
+    async fn rescan_index(
+        &self,
+        index: &SearchIndex,
+        config: &SearchIndexConfig,
+    ) -> anyhow::Result<()> {
+        let started = Instant::now();
+        let since = index.sequence.load(Ordering::Relaxed);
+        let (root, mut prefixes) = self.list_top().await?;
+        prefixes.retain(|x| x != TRASH_PREFIX);
+        index.replace_where(|key| top_folder(key).is_empty(), root, since);
+        let full = Duration::from_secs(config.full_rescan_interval);
+        let mut listed = 0;
+        for prefix in &prefixes {
+            let fingerprint = self.folder_fingerprint(prefix).await?;
+            let scan = index.folders.lock().unwrap().get(prefix).copied();
+            if scan.is_some_and(|x| x.fingerprint == fingerprint && x.listed.elapsed() < full) {
+                continue;
+            }
+            let since = index.sequence.load(Ordering::Relaxed);
+            let listing = Instant::now();
+            let objects = self.list_all(prefix).await?;
+            index.replace_folder(prefix, objects, since);
+            index.folders.lock().unwrap().insert(
+                prefix.clone(),
+                FolderScan {
+                    fingerprint,
+                    listed: listing,
+                },
+            );
+            listed += 1;
+        }
+        // top-level folders removed since the last rescan
+        let folders: HashSet<&str> = prefixes.iter().map(|x| x.as_str()).collect();
+        index.replace_where(
+            |key| {
+                let folder = top_folder(key);
+                !folder.is_empty() && !folders.contains(folder)
+            },
+            Vec::new(),
+            since,
+        );
+        index
+            .folders
+            .lock()
+            .unwrap()
+            .retain(|x, _| folders.contains(x.as_str()));
+        index.keys.write().unwrap().writes.retain(|_, x| *x > since);
+        index.ready.store(true, Ordering::Relaxed);
+        info!(
+            "Search index of {} rescanned in {:?}, {listed} of {} folders listed, {} keys",
+            self.config.alias,
+            started.elapsed(),
+            prefixes.len(),
+            index.len()
+        );
+        Ok(())
+    }
+
+    /// Hash of the first page of the folder's first level: files with their size, time and
+    /// ETag, and subfolders
+    async fn folder_fingerprint(&self, prefix: &str) -> anyhow::Result<u64> {
+        Ok(fingerprint(&self.list_level_page(prefix).await?))
+    }
+
+    /// Loads the index file if there is one, then rescans the bucket every `rescan_interval`.
+    /// The loop ends once the client is dropped
+    pub fn spawn_index(self: &Arc<Self>) {
+        let client = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut first = true;
+            loop {
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                let (Some(index), Some(config)) =
+                    (client.index.clone(), client.config.search_index.clone())
+                else {
+                    break;
+                };
+                if first {
+                    if let Some(path) = config.path.clone().filter(|x| Path::new(x).exists()) {
+                        let loading = index.clone();
+                        match blocking(move || loading.load(&path)).await {
+                            Ok(_) => info!(
+                                "Search index of {} loaded, {} keys",
+                                client.config.alias,
+                                index.len()
+                            ),
+                            Err(e) => {
+                                warn!("Cannot load search index of {}: {e}", client.config.alias)
+                            }
+                        }
+                    }
+                    first = false;
+                }
+                match client.rescan_index(&index, &config).await {
+                    Err(e) => warn!("Cannot rescan search index of {}: {e}", client.config.alias),
+                    Ok(_) => {
+                        if let Some(path) = config.path.clone() {
+                            let saving = index.clone();
+                            if let Err(e) = blocking(move || saving.save(&path)).await {
+                                warn!("Cannot save search index of {}: {e}", client.config.alias)
+                            }
+                        }
+                    }
+                }
+                drop(client);
+                tokio::time::sleep(Duration::from_secs(config.rescan_interval.max(1))).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn object(key: &str, size: u64) -> Object {
+        Object {
+            key: key.to_owned(),
+            size,
+            last_modified: "2025-01-01T00:00:00.000Z".to_owned(),
+            e_tag: None,
+            storage_class: None,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn test_replace_keeps_own_writes() {
+        let index = SearchIndex::new("test");
+        index.insert("a/old.txt", 1);
+        let since = index.sequence.load(Ordering::Relaxed);
+        // written while the prefix was listed
+        index.insert("a/new.txt", 2);
+        index.remove(&["a/deleted.txt".to_owned()]);
+        index.replace_folder(
+            "a/",
+            vec![object("a/listed.txt", 3), object("a/deleted.txt", 4)],
+            since,
+        );
+        let mut keys = Vec::new();
+        index.visit("", |key, _| {
+            keys.push(key.to_owned());
+            true
+        });
+        assert_eq!(keys, vec!["a/listed.txt", "a/new.txt"]);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let page = |objects: Vec<Object>, folders: &[&str]| ListBucketResult {
+            name: "bucket".to_owned(),
+            delimiter: Some("/".to_owned()),
+            max_keys: None,
+            prefix: Some("a/".to_owned()),
+            continuation_token: None,
+            encoding_type: None,
+            is_truncated: false,
+            next_continuation_token: None,
+            contents: objects,
+            common_prefixes: Some(
+                folders
+                    .iter()
+                    .map(|x| s3::serde_types::CommonPrefix {
+                        prefix: x.to_string(),
+                    })
+                    .collect(),
+            ),
+        };
+        let listed = fingerprint(&page(vec![object("a/1", 1)], &["a/b/"]));
+        assert_eq!(
+            listed,
+            fingerprint(&page(vec![object("a/1", 1)], &["a/b/"]))
+        );
+        assert_ne!(
+            listed,
+            fingerprint(&page(vec![object("a/1", 2)], &["a/b/"]))
+        );
+        assert_ne!(
+            listed,
+            fingerprint(&page(vec![object("a/1", 1), object("a/2", 1)], &["a/b/"]))
+        );
+        assert_ne!(
+            listed,
+            fingerprint(&page(vec![object("a/1", 1)], &["a/b/", "a/c/"]))
+        );
+    }
+
+    #[test]
+    fn test_visit_chunks() {
+        let index = SearchIndex::new("test");
+        for i in 0..VISIT_CHUNK * 2 + 1 {
+            index.insert(&format!("a/{i:05}"), 1);
+        }
+        index.insert("b/other", 1);
+        let mut keys = Vec::new();
+        index.visit("a/", |key, _| {
+            keys.push(key.to_owned());
+            true
+        });
+        assert_eq!(keys.len(), VISIT_CHUNK * 2 + 1);
+        assert!(keys.windows(2).all(|x| x[0] < x[1]));
+        let mut count = 0;
+        index.visit("", |_, _| {
+            count += 1;
+            count < VISIT_CHUNK + 5
+        });
+        assert_eq!(count, VISIT_CHUNK + 5);
+    }
+}
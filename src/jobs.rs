@@ -0,0 +1,335 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use log::{info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tracing::Instrument;
+
+use crate::health::unix_now;
+
+/// Finished jobs are kept this long for their results, seconds
+const FINISHED_TTL: u64 = 3600;
+const CLEANUP_INTERVAL: u64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug)]
+pub struct JobCancelled;
+
+impl Display for JobCancelled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Job is cancelled")
+    }
+}
+
+impl std::error::Error for JobCancelled {}
+
+/// The job failed but has a result all the same, e.g. the keys a delete left
+#[derive(Debug)]
+pub struct JobFailed {
+    pub message: String,
+    pub result: Value,
+}
+
+impl Display for JobFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for JobFailed {}
+
+/// Operation running in background, independent of the request which started it
+pub struct Job {
+    pub id: String,
+    pub kind: String,
+    /// only this user sees and cancels the job
+    pub user: String,
+    pub bucket: String,
+    pub path: String,
+    created: u64,
+    done: AtomicU64,
+    total: AtomicU64,
+    cancelled: AtomicBool,
+    outcome: std::sync::Mutex<Outcome>,
+}
+
+struct Outcome {
+    status: JobStatus,
+    finished: Option<u64>,
+    error: Option<String>,
+    result: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobReport {
+    pub id: String,
+    pub kind: String,
+    pub bucket: String,
+    pub path: String,
+    pub status: JobStatus,
+    pub created: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished: Option<u64>,
+    /// items processed, of `total` if it's known already
+    pub done: u64,
+    pub total: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+}
+
+impl Job {
+    pub fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    /// Counts processed items
+    pub fn advance(&self, items: u64) {
+        self.done.fetch_add(items, Ordering::Relaxed);
+    }
+
+    /// Fails with `JobCancelled` once the job is cancelled, operations call it between their steps
+    pub fn check(&self) -> anyhow::Result<()> {
+        match self.cancelled.load(Ordering::Relaxed) {
+            true => Err(JobCancelled.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Asks the job to stop, it does after the step in progress
+    pub fn cancel(&self) {
+        info!("Job {} is cancelled", self.id);
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> JobReport {
+        let outcome = self.outcome.lock().unwrap();
+        JobReport {
+            id: self.id.clone(),
+            kind: self.kind.clone(),
+            bucket: self.bucket.clone(),
+            path: self.path.clone(),
+            status: outcome.status,
+            created: self.created,
+            finished: outcome.finished,
+            done: self.done.load(Ordering::Relaxed),
+            total: self.total.load(Ordering::Relaxed),
+            error: outcome.error.clone(),
+            result: outcome.result.clone(),
+        }
+    }
+
+    fn finish(&self, result: anyhow::Result<Value>) {
+        let mut outcome = self.outcome.lock().unwrap();
+        outcome.finished = Some(unix_now());
+        match result {
+            Ok(result) => {
+                outcome.status = JobStatus::Completed;
+                outcome.result = Some(result);
+            }
+            Err(e) => {
+                outcome.status = match e.is::<JobCancelled>() {
+                    true => JobStatus::Cancelled,
+                    false => JobStatus::Failed,
+                };
+                if let Some(failed) = e.downcast_ref::<JobFailed>() {
+                    outcome.result = Some(failed.result.clone());
+                }
+                outcome.error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn expired(&self) -> bool {
+        let outcome = self.outcome.lock().unwrap();
+        outcome
+            .finished
+            .is_some_and(|x| unix_now().saturating_sub(x) > FINISHED_TTL)
+    }
+}
+
+/// Jobs by id. They live in memory only, a restart loses them with the running operations
+pub struct Jobs {
+    jobs: Mutex<HashMap<String, Arc<Job>>>,
+}
+
+impl Jobs {
+    pub fn new() -> Arc<Self> {
+        let jobs = Arc::new(Self {
+            jobs: Mutex::new(HashMap::new()),
+        });
+        jobs.spawn_cleanup();
+        jobs
+    }
+
+    /// Runs `operation` in background, its result is reported as JSON
+    pub async fn spawn<F, Fut>(
+        &self,
+        kind: &str,
+        user: &str,
+        bucket: &str,
+        path: &str,
+        operation: F,
+    ) -> Arc<Job>
+    where
+        F: FnOnce(Arc<Job>) -> Fut,
+        Fut: Future<Output = anyhow::Result<Value>> + Send + 'static,
+    {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let job = Arc::new(Job {
+            id: hex::encode(bytes),
+            kind: kind.to_owned(),
+            user: user.to_owned(),
+            bucket: bucket.to_owned(),
+            path: path.to_owned(),
+            created: unix_now(),
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            outcome: std::sync::Mutex::new(Outcome {
+                status: JobStatus::Running,
+                finished: None,
+                error: None,
+                result: None,
+            }),
+        });
+        info!(
+            "Job {} {kind} of {path} in {bucket} started by {user}",
+            job.id
+        );
+        self.jobs.lock().await.insert(job.id.clone(), job.clone());
+        let future = operation(job.clone());
+        let running = job.clone();
+        tokio::spawn(
+            async move {
+                let result = future.await;
+                if let Err(e) = &result {
+                    warn!("Job {} failed: {e}", running.id);
+                }
+                running.finish(result);
+            }
+            .in_current_span(),
+        );
+        job
+    }
+
+    /// Job of the user, others' jobs are not visible
+    pub async fn get(&self, id: &str, user: &str) -> Option<Arc<Job>> {
+        self.jobs
+            .lock()
+            .await
+            .get(id)
+            .filter(|x| x.user == user)
+            .cloned()
+    }
+
+    /// Jobs of the user, newest first
+    pub async fn list(&self, user: &str) -> Vec<JobReport> {
+        let mut jobs: Vec<JobReport> = self
+            .jobs
+            .lock()
+            .await
+            .values()
+            .filter(|x| x.user == user)
+            .map(|x| x.report())
+            .collect();
+        jobs.sort_by_key(|x| std::cmp::Reverse(x.created));
+        jobs
+    }
+
+    fn spawn_cleanup(self: &Arc<Self>) {
+        let jobs: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(CLEANUP_INTERVAL)).await;
+                let Some(jobs) = jobs.upgrade() else {
+                    break;
+                };
+                jobs.jobs.lock().await.retain(|_, x| !x.expired());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let jobs = Jobs::new();
+        let job = jobs
+            .spawn("test", "alice", "bucket", "path/", |job| async move {
+                job.set_total(1000);
+                loop {
+                    job.check()?;
+                    job.advance(1);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await;
+        assert!(jobs.get(&job.id, "bob").await.is_none());
+        let job = jobs.get(&job.id, "alice").await.unwrap();
+        job.cancel();
+        while job.report().status == JobStatus::Running {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let report = job.report();
+        assert_eq!(report.status, JobStatus::Cancelled);
+        assert!(report.finished.is_some() && report.done < report.total);
+        assert_eq!(jobs.list("alice").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_result() {
+        let jobs = Jobs::new();
+        let job = jobs
+            .spawn("test", "alice", "bucket", "path/", |_| async move {
+                Err(JobFailed {
+                    message: "1 files are not deleted".to_owned(),
+                    result: serde_json::json!({"failed": ["path/a"]}),
+                }
+                .into())
+            })
+            .await;
+        while job.report().status == JobStatus::Running {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let report = job.report();
+        assert_eq!(report.status, JobStatus::Failed);
+        assert_eq!(report.error.as_deref(), Some("1 files are not deleted"));
+        assert_eq!(report.result.unwrap()["failed"][0], "path/a");
+    }
+}
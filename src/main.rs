@@ -16,22 +16,40 @@
 use std::process::exit;
 use std::sync::Arc;
 
-use clap::Parser;
-use log::{error, info};
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
 
 use crate::http::HttpServer;
 
+mod check;
+mod checksum;
+mod client;
 mod config;
+mod health;
 mod http;
+mod index;
+mod jobs;
+mod memory;
+mod metrics;
+mod reload;
 mod s3;
+mod search;
+mod session;
+mod share;
+mod sigv4;
 mod sso;
+mod sts;
+mod telemetry;
+mod trash;
 
 /// S3 Client with web interface and SSO integration
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// path to configuration file
-    #[arg(short, long, default_value = "s3clix.yaml")]
+    #[arg(short, long, default_value = "s3clix.yaml", global = true)]
     config: String,
     /// Threads for multithread runtime
     #[arg(short, long)]
@@ -41,14 +59,67 @@ struct Args {
     single: bool,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Run the server (default)
+    Serve,
+    /// Validate configuration file and exit, non-zero exit code if it has problems
+    CheckConfig {
+        /// Also try to reach every bucket and SSO well-known URL
+        #[arg(long)]
+        probe: bool,
+    },
+    /// Work with files of a running s3clix, authenticated with API token
+    Client(client::ClientArgs),
+}
+
+/// Runtime for the commands other than serve, which log warnings by default
+fn tool_runtime() -> tokio::runtime::Runtime {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(_) => {
+            error!("Tokio runtime tech failed");
+            exit(-1);
+        }
+    }
+}
+
 fn main() {
-    env_logger::init();
     let args = Args::parse();
+    match args.command {
+        Some(Command::CheckConfig { probe }) => {
+            let rt = tool_runtime();
+            if !rt.block_on(check::check_config(&args.config, probe)) {
+                exit(1);
+            }
+            return;
+        }
+        Some(Command::Client(client_args)) => {
+            let rt = tool_runtime();
+            if let Err(e) = rt.block_on(client::run(client_args)) {
+                eprintln!("Error: {e}");
+                exit(1);
+            }
+            return;
+        }
+        Some(Command::Serve) | None => {}
+    }
+    env_logger::init();
     // create tokio runtime
-    let Ok(mut config) = config::Config::new(args.config) else {
-        error!("Configuration not found / not parsed properly");
-        exit(-1);
+    let mut config = match config::Config::new(&args.config) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Configuration not found / not parsed properly: {e}");
+            exit(-1);
+        }
     };
+    for problem in config.lint() {
+        warn!("{problem}");
+    }
     let threads = match config.threads {
         Some(threads) => args.threads.unwrap_or(threads),
         None => args.threads.unwrap_or(10),
@@ -68,13 +139,18 @@ fn main() {
     };
 
     if let Err(e) = rt.block_on(async move {
+        if let Some(tracing) = config.tracing.as_ref() {
+            telemetry::init(tracing)?;
+        }
         if config.is_sso() {
             info!("The application is running in SSO integration mode");
             config.init_sso().await?;
         }
         // now let's shadow the config forever
         let config = Arc::new(config);
-        HttpServer::start(config.clone()).await
+        let result = HttpServer::start(config.clone(), args.config).await;
+        telemetry::shutdown();
+        result
     }) {
         error!("Program exited with error: {}", e)
     };
@@ -0,0 +1,169 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::metrics::METRICS;
+
+/// Process-wide byte budget for transfer buffers
+pub static MEMORY: LazyLock<MemoryBudget> = LazyLock::new(MemoryBudget::new);
+
+/// Permits are KiB, so budgets above 4 GiB fit into u32 permits
+const UNIT: usize = 1024;
+
+#[derive(Debug)]
+pub struct MemoryExhausted;
+
+impl Display for MemoryExhausted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Server is busy: memory budget exhausted, try again later"
+        )
+    }
+}
+
+impl std::error::Error for MemoryExhausted {}
+
+pub struct MemoryBudget {
+    semaphore: Semaphore,
+    /// KiB, 0 means no limit
+    total: AtomicUsize,
+    /// seconds to wait for memory before giving up
+    wait: AtomicU64,
+}
+
+/// Memory borrowed from the budget, returned on drop
+pub struct MemoryPermit {
+    _permit: Option<SemaphorePermit<'static>>,
+    bytes: i64,
+}
+
+impl Drop for MemoryPermit {
+    fn drop(&mut self) {
+        METRICS.memory_used.sub(self.bytes);
+    }
+}
+
+impl MemoryBudget {
+    fn new() -> Self {
+        Self {
+            semaphore: Semaphore::new(0),
+            total: AtomicUsize::new(0),
+            wait: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the budget once at startup, `None` keeps memory unlimited (but still accounted)
+    pub fn init(&self, budget: Option<usize>, wait: u64) {
+        self.set_wait(wait);
+        let Some(budget) = budget else {
+            return;
+        };
+        let units = budget.div_ceil(UNIT).min(u32::MAX as usize);
+        if self
+            .total
+            .compare_exchange(0, units, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            self.semaphore.add_permits(units);
+            METRICS.memory_budget.set(budget as i64);
+        }
+    }
+
+    pub fn set_wait(&self, wait: u64) {
+        self.wait.store(wait, Ordering::Relaxed);
+    }
+
+    /// Borrows `bytes`, waiting for other transfers to return memory at most `wait` seconds.
+    /// Requests bigger than the whole budget take all of it
+    pub async fn acquire(&'static self, bytes: usize) -> anyhow::Result<MemoryPermit> {
+        let total = self.total.load(Ordering::Relaxed);
+        let permit = match total {
+            0 => None,
+            total => {
+                let units = bytes.div_ceil(UNIT).clamp(1, total) as u32;
+                let wait = Duration::from_secs(self.wait.load(Ordering::Relaxed));
+                match tokio::time::timeout(wait, self.semaphore.acquire_many(units)).await {
+                    Ok(permit) => Some(permit?),
+                    Err(_) => {
+                        METRICS.memory_rejections.inc();
+                        return Err(MemoryExhausted.into());
+                    }
+                }
+            }
+        };
+        let bytes = bytes as i64;
+        METRICS.memory_used.add(bytes);
+        Ok(MemoryPermit {
+            _permit: permit,
+            bytes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn budget(bytes: usize) -> &'static MemoryBudget {
+        let budget = Box::leak(Box::new(MemoryBudget::new()));
+        budget.init(Some(bytes), 0);
+        budget
+    }
+
+    #[tokio::test]
+    async fn test_budget() {
+        let budget = budget(4 * UNIT);
+        let first = budget.acquire(2 * UNIT).await.unwrap();
+        let second = budget.acquire(UNIT + 1).await.unwrap();
+        let error = budget.acquire(1).await.err().unwrap();
+        assert!(error.is::<MemoryExhausted>());
+        drop(first);
+        let third = budget.acquire(2 * UNIT).await.unwrap();
+        drop((second, third));
+        // bigger than the budget takes all of it
+        let all = budget.acquire(10 * UNIT).await.unwrap();
+        assert!(budget.acquire(1).await.is_err());
+        drop(all);
+        assert!(budget.acquire(4 * UNIT).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let budget = Box::leak(Box::new(MemoryBudget::new()));
+        budget.init(None, 0);
+        let _held = budget.acquire(usize::MAX / 2).await.unwrap();
+        assert!(budget.acquire(usize::MAX / 2).await.is_ok());
+    }
+
+    /// A permit returned while another transfer waits lets it through within `wait`
+    #[tokio::test]
+    async fn test_wait() {
+        let budget = budget(2 * UNIT);
+        budget.set_wait(5);
+        let held = budget.acquire(2 * UNIT).await.unwrap();
+        let waiting = tokio::spawn(budget.acquire(UNIT));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        drop(held);
+        assert!(waiting.await.unwrap().is_ok());
+    }
+}
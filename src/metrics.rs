@@ -0,0 +1,235 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::sync::LazyLock;
+
+use log::warn;
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, Encoder, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_duration: HistogramVec,
+    pub bytes_uploaded: IntCounterVec,
+    pub bytes_downloaded: IntCounterVec,
+    pub s3_duration: HistogramVec,
+    pub s3_timeouts: IntCounterVec,
+    pub s3_retries: IntCounterVec,
+    pub s3_errors: IntCounterVec,
+    pub sso_refresh: IntCounterVec,
+    pub credentials_refresh: IntCounterVec,
+    pub checksum_failures: IntCounterVec,
+    pub sso_verify_failures: IntCounter,
+    pub active_downloads: IntGauge,
+    pub download_memory: IntGauge,
+    pub memory_budget: IntGauge,
+    pub memory_used: IntGauge,
+    pub memory_rejections: IntCounter,
+    pub search_index_keys: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("s3clix".to_owned()), None)
+            .expect("metrics registry prefix is valid");
+        let metrics = Self {
+            http_requests: IntCounterVec::new(
+                opts!("http_requests_total", "HTTP requests by route and status"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_duration: HistogramVec::new(
+                histogram_opts!(
+                    "http_request_duration_seconds",
+                    "HTTP request latency by route and status",
+                    exponential_buckets(0.005, 2.0, 16).unwrap()
+                ),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            bytes_uploaded: IntCounterVec::new(
+                opts!("uploaded_bytes_total", "Bytes uploaded to S3 per bucket"),
+                &["bucket"],
+            )
+            .unwrap(),
+            bytes_downloaded: IntCounterVec::new(
+                opts!(
+                    "downloaded_bytes_total",
+                    "Bytes downloaded from S3 per bucket"
+                ),
+                &["bucket"],
+            )
+            .unwrap(),
+            s3_duration: HistogramVec::new(
+                histogram_opts!(
+                    "s3_request_duration_seconds",
+                    "S3 call latency including retries",
+                    exponential_buckets(0.005, 2.0, 16).unwrap()
+                ),
+                &["bucket", "operation"],
+            )
+            .unwrap(),
+            s3_timeouts: IntCounterVec::new(
+                opts!("s3_timeouts_total", "S3 calls which hit the bucket timeout"),
+                &["bucket", "operation"],
+            )
+            .unwrap(),
+            s3_retries: IntCounterVec::new(
+                opts!("s3_retries_total", "S3 calls retried after a timeout"),
+                &["bucket", "operation"],
+            )
+            .unwrap(),
+            s3_errors: IntCounterVec::new(
+                opts!("s3_errors_total", "S3 calls finished with an error"),
+                &["bucket", "operation"],
+            )
+            .unwrap(),
+            sso_refresh: IntCounterVec::new(
+                opts!("sso_jwks_refresh_total", "SSO well-known / JWKS refreshes"),
+                &["result"],
+            )
+            .unwrap(),
+            credentials_refresh: IntCounterVec::new(
+                opts!(
+                    "s3_credentials_refresh_total",
+                    "Refreshes of temporary S3 credentials"
+                ),
+                &["bucket", "result"],
+            )
+            .unwrap(),
+            checksum_failures: IntCounterVec::new(
+                opts!(
+                    "checksum_failures_total",
+                    "Uploads and downloads which did not match their checksums"
+                ),
+                &["bucket", "direction"],
+            )
+            .unwrap(),
+            sso_verify_failures: IntCounter::new(
+                "sso_token_verify_failures_total",
+                "SSO tokens which failed verification",
+            )
+            .unwrap(),
+            active_downloads: IntGauge::new("active_downloads", "Streaming downloads in progress")
+                .unwrap(),
+            download_memory: IntGauge::new(
+                "download_memory_bytes",
+                "Memory reserved by streaming downloads (download_memory_pool each)",
+            )
+            .unwrap(),
+            memory_budget: IntGauge::new(
+                "memory_budget_bytes",
+                "Memory budget for transfer buffers, 0 if unlimited",
+            )
+            .unwrap(),
+            memory_used: IntGauge::new(
+                "memory_used_bytes",
+                "Memory borrowed from the budget by uploads and downloads",
+            )
+            .unwrap(),
+            memory_rejections: IntCounter::new(
+                "memory_rejections_total",
+                "Transfers rejected as the memory budget was exhausted",
+            )
+            .unwrap(),
+            search_index_keys: IntGaugeVec::new(
+                opts!("search_index_keys", "Keys in the search index per bucket"),
+                &["bucket"],
+            )
+            .unwrap(),
+            registry,
+        };
+        metrics.register();
+        metrics
+    }
+
+    fn register(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 18] = [
+            Box::new(self.http_requests.clone()),
+            Box::new(self.http_duration.clone()),
+            Box::new(self.bytes_uploaded.clone()),
+            Box::new(self.bytes_downloaded.clone()),
+            Box::new(self.s3_duration.clone()),
+            Box::new(self.s3_timeouts.clone()),
+            Box::new(self.s3_retries.clone()),
+            Box::new(self.s3_errors.clone()),
+            Box::new(self.sso_refresh.clone()),
+            Box::new(self.credentials_refresh.clone()),
+            Box::new(self.checksum_failures.clone()),
+            Box::new(self.sso_verify_failures.clone()),
+            Box::new(self.active_downloads.clone()),
+            Box::new(self.download_memory.clone()),
+            Box::new(self.memory_budget.clone()),
+            Box::new(self.memory_used.clone()),
+            Box::new(self.memory_rejections.clone()),
+            Box::new(self.search_index_keys.clone()),
+        ];
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .expect("metric registered twice");
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            warn!("Metrics encoding failed: {e}");
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
+
+/// Keeps download gauges up to date while a streaming download is alive
+pub struct DownloadGuard {
+    memory: i64,
+}
+
+impl DownloadGuard {
+    pub fn new(memory: usize) -> Self {
+        let memory = memory as i64;
+        METRICS.active_downloads.inc();
+        METRICS.download_memory.add(memory);
+        Self { memory }
+    }
+}
+
+impl Drop for DownloadGuard {
+    fn drop(&mut self) {
+        METRICS.active_downloads.dec();
+        METRICS.download_memory.sub(self.memory);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::METRICS;
+
+    #[test]
+    fn test_render() {
+        METRICS
+            .http_requests
+            .with_label_values(&["GET", "/ready", "200"])
+            .inc();
+        let text = METRICS.render();
+        assert!(text.contains("s3clix_http_requests_total"));
+        assert!(text.contains("route=\"/ready\""));
+    }
+}
@@ -0,0 +1,62 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+use tokio::signal::unix::{signal, SignalKind};
+
+const POLL_INTERVAL: u64 = 5;
+
+fn modified<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// Calls `reload` on SIGHUP and whenever modification time of the file changes.
+/// The file is polled, so it works for K8S ConfigMaps swapping symlinks as well
+pub fn watch<F, Fut>(path: String, reload: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("SIGHUP handler is not installed: {e}");
+                None
+            }
+        };
+        let mut last = modified(&path);
+        let mut ticker = tokio::time::interval(Duration::from_secs(POLL_INTERVAL));
+        loop {
+            tokio::select! {
+                Some(_) = async { hangup.as_mut()?.recv().await } => {
+                    info!("SIGHUP received, reloading configuration");
+                }
+                _ = ticker.tick() => {
+                    if modified(&path) == last {
+                        continue;
+                    }
+                    info!("Configuration file {path} changed, reloading");
+                }
+            }
+            last = modified(&path);
+            reload().await;
+        }
+    });
+}
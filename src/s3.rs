@@ -13,34 +13,92 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.
 **/
-use std::io::ErrorKind;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-use aho_corasick::AhoCorasickBuilder;
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
+use arc_swap::ArcSwap;
 use axum::body::Bytes;
-use axum::extract::BodyStream;
-use futures_util::{Stream, StreamExt, TryStreamExt};
-use log::{debug, info};
+use axum::http::{HeaderMap, HeaderValue};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_util::{Stream, StreamExt};
+use log::{debug, error, info, warn};
 use mime_guess::mime;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use quick_xml::escape::escape;
+use reqwest::Method;
+use s3::command::{Command, Multipart};
 use s3::creds::Credentials;
-use s3::serde_types::{CommonPrefix, Object};
+use s3::error::S3Error;
+use s3::request::request_trait::Request;
+use s3::request::tokio_backend::HyperRequest;
+use s3::serde_types::{CommonPrefix, ListBucketResult, Object};
 use s3::{Bucket, Region};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
-use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tokio::task::{JoinError, JoinSet};
+use tracing::{instrument, trace_span, Instrument};
 
-use crate::config::{Config, HostAccessStyle, S3Bucket, S3Config};
+use crate::checksum::{encode_crc32c, ChecksumMismatch, Checksums, Hasher, CRC32C_TAG, SHA256_TAG};
+use crate::config::{
+    Config, CredentialsSource, HostAccessStyle, S3Bucket, S3Config, MAX_PRESIGN_EXPIRY,
+};
+use crate::health::unix_now;
+use crate::index::SearchIndex;
+use crate::jobs::Job;
+use crate::memory::{MemoryPermit, MEMORY};
+use crate::metrics::METRICS;
+use crate::search::{BadSearch, Search, SearchQuery};
+use crate::sigv4;
+use crate::sts;
+use crate::telemetry;
+use crate::trash::{in_trash, TRASH_PREFIX};
+
+/// Parts of direct uploads are at least this big, S3 allows 10000 parts per upload
+const DIRECT_PART_SIZE: u64 = 16 * 1024 * 1024;
+const DIRECT_MAX_PARTS: u64 = 10000;
+/// DeleteObjects takes at most this many keys
+const DELETE_BATCH: usize = 1000;
+/// Keys listed by a delete preview
+const PREVIEW_SAMPLE: usize = 20;
+/// `x-amz-copy-source` is a URL path, rust-s3 passes it as is
+const COPY_SOURCE_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// CopyObject copies at most 5 GiB, bigger objects are copied in parts of at least this size
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+const COPY_PART_SIZE: u64 = 512 * 1024 * 1024;
+
+/// S3 minimum for every part but the last one
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+const MAX_PARTS: u32 = 10000;
+/// Attempts per part, on top of retries after timeouts
+const PART_TRIES: u64 = 3;
+
+/// Temporary credentials are renewed this long before they expire
+const CREDENTIALS_MARGIN: u64 = 300;
+const CREDENTIALS_RETRY: u64 = 30;
 
 pub struct S3Client {
     pub config: Arc<S3Bucket>,
-    bucket: Box<Bucket>,
+    /// swapped as a whole when credentials are refreshed
+    bucket: ArcSwap<Bucket>,
     upload_memory_pool: usize,
+    workers: usize,
+    /// keys for global search, if the bucket has `search_index`
+    pub index: Option<Arc<SearchIndex>>,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FileList {
     pub path: String,
     pub name: String,
@@ -75,34 +133,309 @@ impl From<CommonPrefix> for FileList {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PresignMethod {
+    #[default]
+    Get,
+    Put,
+}
+
+/// URL to access an object straight in S3
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Presigned {
+    pub url: String,
+    pub method: PresignMethod,
+    /// may differ from the requested one for PUT, see `OverwritePolicy`
+    pub path: String,
+    /// unix time the URL stops working at
+    pub expires: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DirectUpload {
+    /// may differ from the requested one, see `OverwritePolicy`
+    pub path: String,
+    pub upload_id: String,
+    pub part_size: u64,
+    pub parts: Vec<DirectPart>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DirectPart {
+    pub part_number: u32,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UploadedPart {
+    pub part_number: u32,
+    pub etag: String,
+    /// base64 CRC32C S3 verified the part with, none for direct uploads
+    #[serde(default)]
+    pub crc32c: Option<String>,
+}
+
+/// Version of an object or a delete marker
+#[derive(Serialize, Debug, Clone)]
+pub struct ObjectVersion {
+    pub path: String,
+    pub name: String,
+    pub version_id: String,
+    pub size: u64,
+    pub last_modified: String,
+    pub latest: bool,
+    pub delete_marker: bool,
+}
+
+/// Elements of `ListVersionsResult`. All of them go into one list: versions and delete markers
+/// interleave, which named fields of quick-xml cannot take
+#[derive(Deserialize, Debug)]
+struct ListVersionsResult {
+    #[serde(rename = "$value", default)]
+    entries: Vec<VersionEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+enum VersionEntry {
+    Version(VersionXml),
+    DeleteMarker(VersionXml),
+    IsTruncated(bool),
+    NextKeyMarker(String),
+    NextVersionIdMarker(String),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct VersionXml {
+    key: String,
+    version_id: String,
+    is_latest: bool,
+    last_modified: String,
+    #[serde(default)]
+    size: u64,
+}
+
+impl VersionEntry {
+    fn into_version(self) -> Option<ObjectVersion> {
+        let (x, delete_marker) = match self {
+            VersionEntry::Version(x) => (x, false),
+            VersionEntry::DeleteMarker(x) => (x, true),
+            _ => return None,
+        };
+        Some(ObjectVersion {
+            name: strip_prefix(&x.key),
+            path: x.key,
+            version_id: x.version_id,
+            size: x.size,
+            last_modified: x.last_modified,
+            latest: x.is_latest,
+            delete_marker,
+        })
+    }
+}
+
+/// Result of a recursive delete
+#[derive(Serialize, Debug, Default)]
+pub struct DeleteReport {
+    pub deleted: usize,
+    pub failed: Vec<DeleteFailure>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeleteFailure {
+    pub key: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// What a recursive delete would remove
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeletePreview {
+    pub count: usize,
+    pub size: u64,
+    /// first keys
+    pub sample: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeleteResult {
+    #[serde(rename = "Error", default)]
+    errors: Vec<DeleteErrorXml>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct DeleteErrorXml {
+    key: String,
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct CopyPartResult {
+    #[serde(rename = "ETag")]
+    etag: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+struct Tagging {
+    tag_set: TagSet,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct TagSet {
+    #[serde(rename = "Tag", default)]
+    tags: Vec<TagXml>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct TagXml {
+    key: String,
+    value: String,
+}
+
+impl From<DeleteErrorXml> for DeleteFailure {
+    fn from(value: DeleteErrorXml) -> Self {
+        Self {
+            key: value.key,
+            code: value.code,
+            message: value.message,
+        }
+    }
+}
+
 macro_rules! s3_with_timeout {
-    ($tries:expr, $timeout:expr, $expr:expr) => {{
+    ($config:expr, $op:expr, $expr:expr) => {{
         let mut b = 0usize;
-        let timeout = Duration::from_secs($timeout);
-        loop {
-            if let Ok(data) = tokio::time::timeout(timeout, $expr).await {
-                break data.map_err(|x| anyhow!(x));
-            }
-            b += 1;
-            if b > $tries {
-                break Err(anyhow::anyhow!("Operation timeout / tries exceeded"));
+        let timeout = Duration::from_secs($config.timeout);
+        let labels = [$config.alias.as_str(), $op];
+        let started = Instant::now();
+        let span = trace_span!("s3", otel.name = $op, bucket = %$config.alias, retries = 0usize);
+        let result = async {
+            loop {
+                if let Ok(data) = tokio::time::timeout(timeout, $expr).await {
+                    break data.map_err(|x| anyhow!(x));
+                }
+                METRICS.s3_timeouts.with_label_values(&labels).inc();
+                b += 1;
+                if b > $config.tries {
+                    break Err(anyhow::anyhow!("Operation timeout / tries exceeded"));
+                }
+                METRICS.s3_retries.with_label_values(&labels).inc();
+                tracing::Span::current().record("retries", b);
             }
         }
+        .instrument(span)
+        .await;
+        METRICS
+            .s3_duration
+            .with_label_values(&labels)
+            .observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            METRICS.s3_errors.with_label_values(&labels).inc();
+        }
+        result
     }};
 }
+/// The bucket has no credentials yet, rust-s3 panics presigning without them
+#[derive(Debug)]
+pub struct NoCredentials;
+
+impl Display for NoCredentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bucket has no credentials to sign requests with")
+    }
+}
+
+impl std::error::Error for NoCredentials {}
+
+/// Upload with `create_only` found the file created meanwhile
+#[derive(Debug)]
+pub struct ObjectExists(pub String);
+
+impl Display for ObjectExists {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} was created during the upload", self.0)
+    }
+}
+
+impl std::error::Error for ObjectExists {}
+
+/// S3 answered 412 to a conditional request
+#[derive(Debug)]
+struct PreconditionFailed;
+
+impl Display for PreconditionFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S3 responded 412 Precondition Failed")
+    }
+}
+
+impl std::error::Error for PreconditionFailed {}
+
+/// Bucket whose client could not be created, degraded until the configuration changes
+pub struct FailedBucket {
+    pub alias: String,
+    pub error: String,
+}
+
 impl S3Client {
+    /// Client with its background tasks: credential refresh, trash purge and search index
     pub async fn new_from_bucket(
         s3_config: &S3Config,
         bucket_config: Arc<S3Bucket>,
-    ) -> anyhow::Result<Self> {
+    ) -> anyhow::Result<Arc<Self>> {
+        let (client, refresh) = Self::connect(s3_config, bucket_config).await?;
+        if let Some(refresh) = refresh {
+            client.spawn_refresh(refresh);
+        }
+        if client.config.trash.is_some() {
+            client.spawn_purge();
+        }
+        if client.index.is_some() {
+            client.spawn_index();
+        }
+        Ok(client)
+    }
+
+    /// Client without background tasks: credentials aren't refreshed, trash isn't purged and
+    /// the search index isn't built or saved. For one-off checks such as `check-config --probe`
+    pub async fn new_detached(
+        s3_config: &S3Config,
+        bucket_config: Arc<S3Bucket>,
+    ) -> anyhow::Result<Arc<Self>> {
+        Ok(Self::connect(s3_config, bucket_config).await?.0)
+    }
+
+    /// The client and when its credentials are to be refreshed
+    async fn connect(
+        s3_config: &S3Config,
+        bucket_config: Arc<S3Bucket>,
+    ) -> anyhow::Result<(Arc<Self>, Option<Duration>)> {
         let region = Region::from_str(&bucket_config.url)?; // error shall never occur here, but...
-        let creds = Credentials::new(
-            Some(&bucket_config.access_key),
-            Some(&bucket_config.secret_key),
-            None,
-            None,
-            None,
-        )?;
+
+        // without credentials the bucket answers pings with NoCredentials, so it stays degraded
+        let (creds, refresh) = match credentials(&bucket_config).await {
+            Ok(creds) => {
+                let refresh = refresh_in(&creds);
+                (creds, refresh)
+            }
+            Err(e) => {
+                warn!("{e}, retrying in {CREDENTIALS_RETRY}s");
+                (
+                    Credentials::anonymous()?,
+                    Some(Duration::from_secs(CREDENTIALS_RETRY)),
+                )
+            }
+        };
         let mut bucket = Bucket::new(&bucket_config.bucket, region, creds)?;
         match &bucket_config.style {
             HostAccessStyle::Path => bucket.set_path_style(),
@@ -112,49 +445,130 @@ impl S3Client {
         if bucket_config.make_public {
             bucket.add_header("x-amz-acl", "public-read");
         }
-        let s = S3Client {
-            config: bucket_config,
-            bucket,
+        let client = Arc::new(S3Client {
+            bucket: ArcSwap::from_pointee(*bucket),
             upload_memory_pool: s3_config.upload_memory_pool,
-        };
-        s.list("").await?;
-        Ok(s)
+            workers: s3_config.workers,
+            index: bucket_config
+                .search_index
+                .as_ref()
+                .map(|_| SearchIndex::new(&bucket_config.alias)),
+            config: bucket_config,
+        });
+        Ok((client, refresh))
     }
 
-    pub async fn new(config: Arc<Config>) -> anyhow::Result<Vec<S3Client>> {
+    /// Buckets whose client can't be created are returned apart, so the rest are still served
+    pub async fn new(config: Arc<Config>) -> (Vec<Arc<S3Client>>, Vec<FailedBucket>) {
+        Self::reload(&config, &[]).await
+    }
+
+    /// Clients for reloaded configuration: unchanged buckets keep their clients
+    pub async fn reload(
+        config: &Config,
+        current: &[Arc<S3Client>],
+    ) -> (Vec<Arc<S3Client>>, Vec<FailedBucket>) {
         let mut ret = Vec::new();
-        for i in &config.s3.buckets {
-            ret.push(S3Client::new_from_bucket(&config.s3, i.clone()).await?);
+        let mut failed = Vec::new();
+        for bucket in &config.s3.buckets {
+            match current.iter().find(|x| {
+                x.config == *bucket
+                    && x.upload_memory_pool == config.s3.upload_memory_pool
+                    && x.workers == config.s3.workers
+            }) {
+                Some(client) => ret.push(client.clone()),
+                None => match S3Client::new_from_bucket(&config.s3, bucket.clone()).await {
+                    Ok(client) => ret.push(client),
+                    Err(e) => {
+                        error!("Cannot set up bucket {}: {e}", bucket.alias);
+                        failed.push(FailedBucket {
+                            alias: bucket.alias.clone(),
+                            error: e.to_string(),
+                        });
+                    }
+                },
+            }
         }
-        Ok(ret)
+        (ret, failed)
     }
 
-    pub async fn exists(&self, path: &str) -> anyhow::Result<bool> {
-        let data = s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket.list(path.to_owned(), None)
-        )?;
+    fn bucket(&self) -> Arc<Bucket> {
+        self.bucket.load_full()
+    }
 
-        for list_result in data {
-            if list_result
-                .contents
-                .into_iter()
-                .filter(|x| x.key.eq(path))
-                .count()
-                > 0
-            {
-                return Ok(true);
-            }
+    /// Bucket for presigning, NoCredentials while they are missing
+    async fn signing_bucket(&self) -> anyhow::Result<Arc<Bucket>> {
+        let bucket = self.bucket();
+        if bucket.credentials().await?.secret_key.is_none() {
+            return Err(NoCredentials.into());
         }
-        Ok(false)
+        Ok(bucket)
+    }
+
+    /// Renews temporary credentials before they expire, the loop ends once the client is dropped
+    fn spawn_refresh(self: &Arc<Self>, mut sleep: Duration) {
+        let client = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(sleep).await;
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                let (result, next) = match credentials(&client.config).await {
+                    Ok(creds) => {
+                        debug!("Credentials of bucket {} refreshed", client.config.alias);
+                        let next = refresh_in(&creds);
+                        let mut bucket = Bucket::clone(&client.bucket.load());
+                        bucket.set_credentials(creds);
+                        client.bucket.store(Arc::new(bucket));
+                        ("success", next)
+                    }
+                    Err(e) => {
+                        error!("{e}, retrying in {CREDENTIALS_RETRY}s");
+                        ("failure", Some(Duration::from_secs(CREDENTIALS_RETRY)))
+                    }
+                };
+                METRICS
+                    .credentials_refresh
+                    .with_label_values(&[&client.config.alias, result])
+                    .inc();
+                match next {
+                    Some(next) => sleep = next,
+                    None => break,
+                }
+            }
+        });
+    }
+
+    /// Cheapest possible request proving the bucket is reachable
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        self.signing_bucket().await?;
+        s3_with_timeout!(
+            self.config,
+            "list_page",
+            self.bucket()
+                .list_page("".to_owned(), Some("/".to_owned()), None, None, Some(1))
+        )
+        .map(|_| ())
+    }
+
+    /// HEAD of exactly `path`
+    pub async fn exists(&self, path: &str) -> anyhow::Result<bool> {
+        // not found is an answer, not an S3 error
+        s3_with_timeout!(self.config, "head_object", async {
+            match self.bucket().head_object(path).await {
+                Ok((_, status)) => Ok(status == 200),
+                Err(S3Error::HttpFailWithBody(404, _)) => Ok(false),
+                Err(e) => Err(e),
+            }
+        })
     }
     pub async fn list(&self, path: &str) -> anyhow::Result<Vec<FileList>> {
         debug!("s3::list ({})", path);
         let data = s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket.list(path.to_owned(), Some("/".to_owned()))
+            self.config,
+            "list",
+            self.bucket().list(path.to_owned(), Some("/".to_owned()))
         )?;
 
         let mut folders = Vec::new();
@@ -177,55 +591,126 @@ impl S3Client {
             list_result
                 .common_prefixes
                 .into_iter()
-                .for_each(|x| x.into_iter().for_each(|x| folders.push(x.into())));
+                .flatten()
+                .filter(|x| x.prefix != TRASH_PREFIX)
+                .for_each(|x| folders.push(x.into()));
         }
         Ok([folders, files].concat())
     }
 
-    pub async fn search(&self, pattern: &str) -> anyhow::Result<Vec<FileList>> {
-        debug!("s3::search ({})", pattern);
-        if pattern.is_empty() || pattern.len() < 3 {
-            return Ok(Vec::new()); // do not list all :)
-        }
+    /// Every object under `prefix`, folders included
+    pub async fn list_all(&self, prefix: &str) -> anyhow::Result<Vec<Object>> {
         let data = s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket.list("".to_owned(), None)
+            self.config,
+            "list",
+            self.bucket().list(prefix.to_owned(), None)
         )?;
+        Ok(data.into_iter().flat_map(|x| x.contents).collect())
+    }
 
-        let ac = AhoCorasickBuilder::new()
-            .ascii_case_insensitive(true)
-            .build([pattern])?;
+    /// Objects in the bucket root and the top-level folders, all pages
+    pub async fn list_top(&self) -> anyhow::Result<(Vec<Object>, Vec<String>)> {
+        let data = s3_with_timeout!(
+            self.config,
+            "list",
+            self.bucket().list(String::new(), Some("/".to_owned()))
+        )?;
+        let mut objects = Vec::new();
+        let mut prefixes = Vec::new();
+        for page in data {
+            objects.extend(page.contents);
+            prefixes.extend(
+                page.common_prefixes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|x| x.prefix),
+            );
+        }
+        Ok((objects, prefixes))
+    }
 
-        Ok(data
-            .into_iter()
-            .flat_map(|x| x.contents)
-            .filter(|x| x.size > 0)
-            .filter(|x| ac.is_match(&x.key))
-            .map(|x| x.into())
-            .collect())
+    /// First page of the files and folders right in `prefix`
+    pub async fn list_level_page(&self, prefix: &str) -> anyhow::Result<ListBucketResult> {
+        let (page, _) = s3_with_timeout!(
+            self.config,
+            "list_page",
+            self.bucket()
+                .list_page(prefix.to_owned(), Some("/".to_owned()), None, None, None)
+        )?;
+        Ok(page)
+    }
+
+    /// Size of the object from HEAD
+    pub async fn size(&self, path: &str) -> anyhow::Result<u64> {
+        let (head, _) =
+            s3_with_timeout!(self.config, "head_object", self.bucket().head_object(path))?;
+        Ok(head.content_length.unwrap_or_default() as u64)
     }
 
-    pub async fn upload_serial(&self, path: &str, mut stream: BodyStream) -> anyhow::Result<()> {
+    /// Answered from the search index once it is built, otherwise the prefix is listed
+    /// up to the limit
+    pub async fn search(&self, query: &SearchQuery) -> anyhow::Result<Search> {
+        debug!("s3::search ({query:?})");
+        let mut search = Search::new(query)?;
+        let prefix = search.prefix().to_owned();
+        if let Some(index) = self.index.as_ref().filter(|x| x.is_ready()) {
+            index.visit(&prefix, |key, entry| {
+                search.offer(key, entry.size, &entry.last_modified)
+            });
+            return Ok(search);
+        }
+        if query.pattern.is_empty() && prefix.is_empty() {
+            return Err(BadSearch(
+                "An empty pattern needs a prefix unless the search index is built".to_owned(),
+            )
+            .into());
+        }
+        // page by page, the listing stops at the limit
+        let mut token = None;
+        loop {
+            let (page, _) = s3_with_timeout!(
+                self.config,
+                "list_page",
+                self.bucket()
+                    .list_page(prefix.clone(), None, token.clone(), None, None)
+            )?;
+            for object in page.contents {
+                if !search.offer(&object.key, object.size, &object.last_modified) {
+                    return Ok(search);
+                }
+            }
+            match page.next_continuation_token {
+                Some(next) => token = Some(next),
+                None => return Ok(search),
+            }
+        }
+    }
+
+    #[instrument(level = "trace", skip(self, stream), fields(bucket = %self.config.alias))]
+    pub async fn upload_serial(
+        &self,
+        path: &str,
+        mut stream: impl Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+        expected: &Checksums,
+        create_only: bool,
+    ) -> anyhow::Result<Checksums> {
         debug!("s3::upload(2) for 1 stream to {path}");
-        let mime_type = match self.config.guess_mime {
-            true => mime_guess::from_path(path).first_or_octet_stream(),
-            false => mime::APPLICATION_OCTET_STREAM,
-        };
-        // let's create thread
-        // then upload data there
-        let mp = s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket
-                .initiate_multipart_upload(path, mime_type.as_ref())
-        )?;
+        let _memory = MEMORY.acquire(self.upload_memory_pool).await?;
+        let upload_id = self.start_upload(path, true).await?;
+        let uploaded = METRICS
+            .bytes_uploaded
+            .with_label_values(&[&self.config.alias]);
         let f = async {
             let mut part_number = 1u32;
             let mut buf = Vec::with_capacity(self.upload_memory_pool);
             let mut parts = Vec::new();
+            let mut hasher = Hasher::new();
+            let mut size = 0u64;
             while let Some(data) = stream.next().await {
                 let data = data?;
+                uploaded.inc_by(data.len() as u64);
+                size += data.len() as u64;
+                hasher.update(&data);
                 let mut last = data.len();
                 if data.len() + buf.len() > buf.capacity() {
                     last = buf.capacity() - buf.len();
@@ -234,15 +719,15 @@ impl S3Client {
                 if buf.len() == buf.capacity() {
                     debug!("Uploading part {part_number} of {path} size {}", buf.len());
                     parts.push(
-                        self.bucket
-                            .put_multipart_chunk(
-                                buf,
-                                path,
-                                part_number,
-                                &mp.upload_id,
-                                mime_type.as_ref(),
-                            )
-                            .await?,
+                        put_part(
+                            self.config.clone(),
+                            self.bucket(),
+                            path.to_owned(),
+                            upload_id.clone(),
+                            part_number,
+                            buf.into(),
+                        )
+                        .await?,
                     );
                     debug!("Uploaded part {part_number} of {path}");
                     part_number += 1;
@@ -258,59 +743,524 @@ impl S3Client {
                     buf.len()
                 );
                 parts.push(
-                    self.bucket
-                        .put_multipart_chunk(
-                            buf.clone(),
-                            path,
-                            part_number,
-                            &mp.upload_id,
-                            mime_type.as_ref(),
-                        )
-                        .await?,
+                    put_part(
+                        self.config.clone(),
+                        self.bucket(),
+                        path.to_owned(),
+                        upload_id.clone(),
+                        part_number,
+                        buf.into(),
+                    )
+                    .await?,
                 );
             }
+            let checksums = hasher.finish();
+            self.verify("upload", &checksums, expected)?;
             debug!(
                 "Completing multipart upload for {path} with {} parts",
                 parts.len()
             );
-            s3_with_timeout!(
-                self.config.tries,
-                self.config.timeout,
-                self.bucket
-                    .complete_multipart_upload(path, &mp.upload_id, parts.clone())
-            )?;
-            Ok(())
+            self.complete_multipart(path, &upload_id, &parts, create_only)
+                .await?;
+            Ok((checksums, size))
+        }
+        .await;
+        match f {
+            Ok((checksums, size)) => {
+                self.store_checksums(path, &checksums).await;
+                self.index_put(path, size);
+                Ok(checksums)
+            }
+            Err(e) => {
+                if let Err(abort) = self.abort_upload(path, &upload_id).await {
+                    warn!("Cannot abort upload {upload_id} of {path}: {abort}");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Up to `workers` parts are uploaded at once, the parts in flight and the one being read
+    /// fit into `upload_memory_pool` unless it is smaller than two minimal parts
+    #[instrument(level = "trace", skip(self, stream), fields(bucket = %self.config.alias))]
+    pub async fn upload_parallel(
+        &self,
+        path: &str,
+        mut stream: impl Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+        expected: &Checksums,
+        create_only: bool,
+    ) -> anyhow::Result<Checksums> {
+        let workers = self.workers.max(1);
+        let part_size = (self.upload_memory_pool / (workers + 1)).max(MIN_PART_SIZE);
+        let in_flight = (self.upload_memory_pool / part_size)
+            .saturating_sub(1)
+            .clamp(1, workers);
+        debug!(
+            "s3::upload for 1 stream to {path}, parts of {part_size} bytes, {in_flight} at once"
+        );
+        // memory for the part being read, every part takes it along to its upload task.
+        // It is borrowed once the part starts filling, never while a full part waits,
+        // so uploads can't hold the whole budget waiting for each other
+        let mut memory = Some(MEMORY.acquire(part_size).await?);
+        let upload_id = self.start_upload(path, true).await?;
+        let uploaded = METRICS
+            .bytes_uploaded
+            .with_label_values(&[&self.config.alias]);
+        let f = async {
+            let mut tasks = JoinSet::new();
+            let mut parts = Vec::new();
+            let mut part_number = 1u32;
+            let mut buf = Vec::with_capacity(part_size);
+            let mut hasher = Hasher::new();
+            let mut size = 0u64;
+            let spawn = |tasks: &mut JoinSet<_>, part_number: u32, buf: Vec<u8>, memory| {
+                let buf = Bytes::from(buf);
+                let upload = put_part(
+                    self.config.clone(),
+                    self.bucket(),
+                    path.to_owned(),
+                    upload_id.clone(),
+                    part_number,
+                    buf,
+                );
+                tasks.spawn(async move {
+                    let _memory: Option<MemoryPermit> = memory;
+                    upload.await
+                });
+            };
+            while let Some(data) = stream.next().await {
+                let data = data?;
+                uploaded.inc_by(data.len() as u64);
+                size += data.len() as u64;
+                hasher.update(&data);
+                let mut data = data.as_ref();
+                while !data.is_empty() {
+                    if buf.is_empty() && part_number > MAX_PARTS {
+                        bail!("File is bigger than {MAX_PARTS} parts of {part_size} bytes");
+                    }
+                    if memory.is_none() {
+                        memory = Some(MEMORY.acquire(part_size).await?);
+                        buf.reserve_exact(part_size);
+                    }
+                    let take = (part_size - buf.len()).min(data.len());
+                    buf.extend_from_slice(&data[..take]);
+                    data = &data[take..];
+                    if buf.len() < part_size {
+                        continue;
+                    }
+                    while tasks.len() >= in_flight {
+                        parts.push(joined(tasks.join_next().await)?);
+                    }
+                    let full = std::mem::take(&mut buf);
+                    spawn(&mut tasks, part_number, full, memory.take());
+                    part_number += 1;
+                }
+            }
+            // an empty file is one empty part
+            if !buf.is_empty() || part_number == 1 {
+                spawn(&mut tasks, part_number, buf, memory);
+            }
+            while !tasks.is_empty() {
+                parts.push(joined(tasks.join_next().await)?);
+            }
+            parts.sort_by_key(|x| x.part_number);
+            let checksums = hasher.finish();
+            self.verify("upload", &checksums, expected)?;
+            debug!(
+                "Completing multipart upload for {path} with {} parts",
+                parts.len()
+            );
+            self.complete_multipart(path, &upload_id, &parts, create_only)
+                .await?;
+            Ok((checksums, size))
         }
         .await;
+        // parts still in flight are cancelled as the JoinSet is dropped
         match f {
-            Ok(_) => Ok(()),
+            Ok((checksums, size)) => {
+                self.store_checksums(path, &checksums).await;
+                self.index_put(path, size);
+                Ok(checksums)
+            }
             Err(e) => {
-                self.bucket.abort_upload(path, &mp.upload_id).await?;
+                if let Err(abort) = self.abort_upload(path, &upload_id).await {
+                    warn!("Cannot abort upload {upload_id} of {path}: {abort}");
+                }
                 Err(e)
             }
         }
     }
+    /// Starts multipart upload of `size` bytes and presigns a PUT URL for every part
+    #[instrument(level = "trace", skip(self), fields(bucket = %self.config.alias))]
+    pub async fn direct_upload(&self, path: &str, size: u64) -> anyhow::Result<DirectUpload> {
+        let part_size = DIRECT_PART_SIZE.max(size.div_ceil(DIRECT_MAX_PARTS));
+        let count = size.div_ceil(part_size).max(1) as u32;
+        let bucket = self.signing_bucket().await?;
+        let expiry = self.config.direct_upload_expiry.min(MAX_PRESIGN_EXPIRY) as u32;
+        // parts don't pass s3clix, there are no checksums to send with them
+        let upload_id = self.start_upload(path, false).await?;
+        let mut parts = Vec::with_capacity(count as usize);
+        for part_number in 1..=count {
+            let queries = HashMap::from([
+                ("partNumber".to_owned(), part_number.to_string()),
+                ("uploadId".to_owned(), upload_id.clone()),
+            ]);
+            let url = bucket
+                .presign_put(path, expiry, None, Some(queries))
+                .await?;
+            parts.push(DirectPart { part_number, url });
+        }
+        info!(
+            "Direct upload of {path} started, {count} parts of {part_size} bytes, id {upload_id}"
+        );
+        Ok(DirectUpload {
+            path: path.to_owned(),
+            upload_id,
+            part_size,
+            parts,
+        })
+    }
 
-    pub async fn upload_parallel(&self, path: &str, stream: BodyStream) -> anyhow::Result<()> {
-        debug!("s3::upload for 1 stream to {path}");
+    /// Presigns GET or PUT of `path` valid for `expiry` seconds, or until temporary credentials
+    /// expire if it's sooner: S3 refuses the URL after that anyway
+    pub async fn presign(
+        &self,
+        path: &str,
+        method: PresignMethod,
+        expiry: u32,
+    ) -> anyhow::Result<Presigned> {
+        let bucket = self.signing_bucket().await?;
+        let expiry = match valid_for(&bucket.credentials().await?) {
+            Some(0) => return Err(NoCredentials.into()),
+            Some(valid) => expiry.min(valid.try_into().unwrap_or(u32::MAX)),
+            None => expiry,
+        };
+        let url = match method {
+            PresignMethod::Get => bucket.presign_get(path, expiry, None).await?,
+            PresignMethod::Put => bucket.presign_put(path, expiry, None, None).await?,
+        };
+        info!("Presigned {method:?} of {path} for {expiry}s");
+        Ok(Presigned {
+            url,
+            method,
+            path: path.to_owned(),
+            expires: unix_now() + expiry as u64,
+        })
+    }
+
+    /// Starts multipart upload, returns its id. With `checksums` every part has to come
+    /// with its CRC32C, as `put_part` sends it
+    pub async fn start_upload(&self, path: &str, checksums: bool) -> anyhow::Result<String> {
         let mime_type = match self.config.guess_mime {
             true => mime_guess::from_path(path).first_or_octet_stream(),
             false => mime::APPLICATION_OCTET_STREAM,
         };
+        let mut bucket = self.bucket();
+        if checksums && self.config.s3_checksums {
+            let mut headers = bucket.extra_headers.clone();
+            headers.insert(
+                "x-amz-checksum-algorithm",
+                HeaderValue::from_static("CRC32C"),
+            );
+            bucket = Arc::new(bucket.with_extra_headers(headers)?);
+        }
+        let mp = s3_with_timeout!(
+            self.config,
+            "initiate_multipart_upload",
+            bucket.initiate_multipart_upload(path, mime_type.as_ref())
+        )?;
+        Ok(mp.upload_id)
+    }
 
-        let mut ar = stream
-            .map_err(|x| std::io::Error::new(ErrorKind::BrokenPipe, x))
-            .into_async_read()
-            .compat();
+    /// Uploads one part of a multipart upload, returns its ETag
+    #[instrument(level = "trace", skip(self, data), fields(bucket = %self.config.alias, size = data.len()))]
+    pub async fn upload_part(
+        &self,
+        path: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: Vec<u8>,
+    ) -> anyhow::Result<String> {
+        let size = data.len() as u64;
+        let part = put_part(
+            self.config.clone(),
+            self.bucket(),
+            path.to_owned(),
+            upload_id.to_owned(),
+            part_number,
+            data.into(),
+        )
+        .await?;
+        METRICS
+            .bytes_uploaded
+            .with_label_values(&[&self.config.alias])
+            .inc_by(size);
+        Ok(part.etag)
+    }
 
-        s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket
-                .put_object_stream_with_content_type(&mut ar, path, mime_type.clone())
+    /// Whether `upload_id` is an unfinished multipart upload of exactly `path`
+    pub async fn has_upload(&self, path: &str, upload_id: &str) -> anyhow::Result<bool> {
+        let uploads = s3_with_timeout!(
+            self.config,
+            "list_multiparts_uploads",
+            self.bucket().list_multiparts_uploads(Some(path), None)
         )?;
+        Ok(uploads
+            .iter()
+            .flat_map(|x| x.uploads.iter())
+            .any(|x| x.key == path && x.id == upload_id))
+    }
+
+    /// Completes an upload whose parts were sent separately, `size` is `None` if they went
+    /// past s3clix. With `create_only` an existing file is kept, `ObjectExists`
+    #[instrument(level = "trace", skip(self, parts), fields(bucket = %self.config.alias))]
+    pub async fn complete_upload(
+        &self,
+        path: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+        size: Option<u64>,
+        create_only: bool,
+    ) -> anyhow::Result<()> {
+        self.complete_multipart(path, upload_id, &parts, create_only)
+            .await?;
+        info!("Multipart upload of {path} completed");
+        match size {
+            Some(size) => self.index_put(path, size),
+            None => self.index_head(path).await,
+        }
+        Ok(())
+    }
+
+    /// rust-s3 can't pass part checksums to CompleteMultipartUpload, the request is signed here.
+    /// With `create_only` S3 keeps an existing file (`If-None-Match: *`), fails with `ObjectExists`
+    async fn complete_multipart(
+        &self,
+        path: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+        create_only: bool,
+    ) -> anyhow::Result<()> {
+        let query = format!(
+            "{}?uploadId={}",
+            s3::signing::uri_encode(path, false),
+            utf8_percent_encode(upload_id, COPY_SOURCE_ENCODE)
+        );
+        let text = s3_with_timeout!(
+            self.config,
+            "complete_multipart_upload",
+            self.post(&query, complete_request(parts), create_only)
+        )
+        .map_err(|e| match e.is::<PreconditionFailed>() {
+            true => ObjectExists(path.to_owned()).into(),
+            false => e,
+        })?;
+        // errors after the processing started come with 200
+        if text.contains("<Error>") {
+            bail!("S3 cannot complete upload of {path}: {text}");
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self), fields(bucket = %self.config.alias))]
+    pub async fn abort_upload(&self, path: &str, upload_id: &str) -> anyhow::Result<()> {
+        s3_with_timeout!(
+            self.config,
+            "abort_upload",
+            self.bucket().abort_upload(path, upload_id)
+        )
+    }
+
+    /// Versions and delete markers of `path` as S3 lists them. For a folder (ending with `/`)
+    /// of the files right in it, deleted ones included
+    #[instrument(level = "trace", skip(self), fields(bucket = %self.config.alias))]
+    pub async fn versions(&self, path: &str) -> anyhow::Result<Vec<ObjectVersion>> {
+        debug!("s3::versions ({})", path);
+        let folder = path.is_empty() || path.ends_with('/');
+        let mut versions = Vec::new();
+        self.signing_bucket().await?;
+        let mut marker: Option<(String, String)> = None;
+        loop {
+            let query = list_versions_query(path, marker.take());
+            let text = s3_with_timeout!(
+                self.config,
+                "list_versions",
+                self.send_signed(Method::GET, &query, HeaderMap::new(), String::new())
+            )?;
+            let result: ListVersionsResult = quick_xml::de::from_str(&text)?;
+            let (mut truncated, mut next_key, mut next_version_id) = (false, None, None);
+            for entry in result.entries {
+                match entry {
+                    VersionEntry::IsTruncated(x) => truncated = x,
+                    VersionEntry::NextKeyMarker(x) => next_key = Some(x),
+                    VersionEntry::NextVersionIdMarker(x) => next_version_id = Some(x),
+                    entry => versions.extend(
+                        entry
+                            .into_version()
+                            .filter(|x| folder || x.path == path)
+                            .filter(|x| !x.path.ends_with("/.placeholder")),
+                    ),
+                }
+            }
+            match (truncated, next_key, next_version_id) {
+                (true, Some(key), Some(version_id)) => marker = Some((key, version_id)),
+                _ => break,
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Existing version of exactly `path`: name, size and mime as `prepare_download`
+    pub async fn prepare_version_download(
+        &self,
+        path: &str,
+        version_id: &str,
+    ) -> anyhow::Result<(String, u64, String)> {
+        let version = self
+            .versions(path)
+            .await?
+            .into_iter()
+            .find(|x| x.path == path && x.version_id == version_id)
+            .filter(|x| !x.delete_marker)
+            .ok_or_else(|| anyhow!("Version not found"))?;
+        let mime = mime_guess::from_path(&version.name)
+            .first_or_octet_stream()
+            .as_ref()
+            .to_owned();
+        Ok((version.name, version.size, mime))
+    }
+
+    /// Copies the version of `size` bytes over the current one, which becomes a version itself.
+    /// Object tags, checksums among them, are copied along
+    pub async fn restore_version(
+        &self,
+        path: &str,
+        version_id: &str,
+        size: u64,
+    ) -> anyhow::Result<()> {
+        info!("Restoring {path} version {version_id}");
+        self.copy(path, Some(version_id), size, path).await
+    }
+
+    /// Server-side copy of `size` bytes. S3 copies objects up to 5 GiB in one request,
+    /// bigger ones are copied in parts
+    pub async fn copy(
+        &self,
+        from: &str,
+        version_id: Option<&str>,
+        size: u64,
+        to: &str,
+    ) -> anyhow::Result<()> {
+        let mut source = utf8_percent_encode(from, COPY_SOURCE_ENCODE).to_string();
+        if let Some(version_id) = version_id {
+            source = format!(
+                "{source}?versionId={}",
+                utf8_percent_encode(version_id, NON_ALPHANUMERIC)
+            );
+        }
+        match size > MAX_COPY_SIZE {
+            true => self.copy_parts(from, version_id, &source, size, to).await?,
+            false => {
+                s3_with_timeout!(
+                    self.config,
+                    "copy_object",
+                    self.bucket().copy_object_internal(&source, to)
+                )?;
+            }
+        }
+        self.index_put(to, size);
+        Ok(())
+    }
+
+    /// UploadPartCopy of `source`, `from` encoded as `copy_object_internal` takes it.
+    /// Unlike CopyObject it doesn't copy tags, so they are copied after
+    async fn copy_parts(
+        &self,
+        from: &str,
+        version_id: Option<&str>,
+        source: &str,
+        size: u64,
+        to: &str,
+    ) -> anyhow::Result<()> {
+        let part_size = COPY_PART_SIZE.max(size.div_ceil(MAX_PARTS as u64));
+        let copy_source = HeaderValue::from_str(&format!("{}/{source}", self.bucket().name()))?;
+        let tags = self.object_tags(from, version_id).await?;
+        let upload_id = self.start_upload(to, false).await?;
+        debug!("Copying {source} to {to} in parts of {part_size} bytes");
+        let f = async {
+            let mut parts = Vec::new();
+            for (i, start) in (0..size).step_by(part_size as usize).enumerate() {
+                let part_number = i as u32 + 1;
+                let end = (start + part_size).min(size) - 1;
+                let mut headers = HeaderMap::new();
+                headers.insert("x-amz-copy-source", copy_source.clone());
+                headers.insert(
+                    "x-amz-copy-source-range",
+                    HeaderValue::from_str(&format!("bytes={start}-{end}"))?,
+                );
+                let query = format!(
+                    "{}?partNumber={part_number}&uploadId={}",
+                    s3::signing::uri_encode(to, false),
+                    utf8_percent_encode(&upload_id, NON_ALPHANUMERIC)
+                );
+                let text = s3_with_timeout!(
+                    self.config,
+                    "upload_part_copy",
+                    self.send_signed(Method::PUT, &query, headers.clone(), String::new())
+                )?;
+                let result: CopyPartResult = quick_xml::de::from_str(&text)
+                    .map_err(|_| anyhow!("S3 cannot copy part {part_number} to {to}: {text}"))?;
+                parts.push(UploadedPart {
+                    part_number,
+                    etag: result.etag,
+                    crc32c: None,
+                });
+            }
+            self.complete_multipart(to, &upload_id, &parts, false).await
+        }
+        .await;
+        if let Err(e) = f {
+            if let Err(abort) = self.abort_upload(to, &upload_id).await {
+                warn!("Cannot abort upload {upload_id} of {to}: {abort}");
+            }
+            return Err(e);
+        }
+        if !tags.is_empty() {
+            let tags: Vec<(&str, &str)> = tags
+                .iter()
+                .map(|x| (x.key.as_str(), x.value.as_str()))
+                .collect();
+            s3_with_timeout!(
+                self.config,
+                "put_object_tagging",
+                self.bucket().put_object_tagging(to, &tags)
+            )?;
+        }
         Ok(())
     }
+
+    /// Tags of the object or of its version, rust-s3 reads current versions only
+    async fn object_tags(
+        &self,
+        path: &str,
+        version_id: Option<&str>,
+    ) -> anyhow::Result<Vec<TagXml>> {
+        let mut query = format!("{}?tagging", s3::signing::uri_encode(path, false));
+        if let Some(version_id) = version_id {
+            query = format!(
+                "{query}&versionId={}",
+                utf8_percent_encode(version_id, NON_ALPHANUMERIC)
+            );
+        }
+        let text = s3_with_timeout!(
+            self.config,
+            "get_object_tagging",
+            self.send_signed(Method::GET, &query, HeaderMap::new(), String::new())
+        )?;
+        let tagging: Tagging = quick_xml::de::from_str(&text)?;
+        Ok(tagging.tag_set.tags)
+    }
+
     pub async fn prepare_download(&self, path: &str) -> anyhow::Result<(String, u64, String)> {
         let mut file = self.list(path).await?;
         if file.len() != 1 {
@@ -327,36 +1277,181 @@ impl S3Client {
         let size = file.size;
         Ok((name, size, mime))
     }
+    /// Streams the object (or its `version_id`) into `stream`, verifying it against `stored`
+    /// SHA-256 or a plain MD5 ETag.
+    /// The last chunk is held back until the check passes, so a corrupted download ends truncated
+    #[instrument(level = "trace", skip(self, stream, stored), fields(bucket = %self.config.alias))]
     pub async fn download<W: AsyncWrite + Send + Unpin>(
         &self,
         path: &str,
+        version_id: Option<&str>,
         mut stream: W,
+        stored: &Checksums,
     ) -> anyhow::Result<()> {
         info!("downloading file: {}", path);
+        let response = self.get_object(path, version_id).await?;
+        let downloaded = METRICS
+            .bytes_downloaded
+            .with_label_values(&[&self.config.alias]);
+        let mut sha256 = stored.sha256.as_ref().map(|_| Sha256::new());
+        let mut md5 = match sha256 {
+            Some(_) => None,
+            None => md5_etag(&response).map(|x| (md5::Context::new(), x)),
+        };
+        let mut pending: Option<Bytes> = None;
+        let mut s = response.bytes_stream();
+        while let Some(b) = StreamExt::next(&mut s).await {
+            let b = b?;
+            if let Some(sha256) = &mut sha256 {
+                sha256.update(&b);
+            }
+            if let Some((md5, _)) = &mut md5 {
+                md5.consume(&b);
+            }
+            if let Some(previous) = pending.replace(b) {
+                stream.write_all(&previous).await?;
+                downloaded.inc_by(previous.len() as u64);
+            }
+        }
+        if let Some(sha256) = sha256 {
+            let actual = Checksums {
+                sha256: Some(STANDARD.encode(sha256.finalize())),
+                crc32c: None,
+            };
+            let expected = Checksums {
+                sha256: stored.sha256.clone(),
+                crc32c: None,
+            };
+            self.verify("download", &actual, &expected)?;
+        }
+        if let Some((md5, etag)) = md5 {
+            let actual = hex::encode(md5.compute().0);
+            if actual != etag {
+                METRICS
+                    .checksum_failures
+                    .with_label_values(&[&self.config.alias, "download"])
+                    .inc();
+                return Err(ChecksumMismatch(format!("MD5 is {actual}, ETag is {etag}")).into());
+            }
+        }
+        if let Some(last) = pending {
+            stream.write_all(&last).await?;
+            downloaded.inc_by(last.len() as u64);
+        }
+        Ok(())
+    }
+
+    async fn get_object(
+        &self,
+        path: &str,
+        version_id: Option<&str>,
+    ) -> anyhow::Result<reqwest::Response> {
+        let queries = version_id.map(|x| HashMap::from([("versionId".to_owned(), x.to_owned())]));
+        let bucket = self.signing_bucket().await?;
         let url = s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket.presign_get(path, 86400, None)
+            self.config,
+            "presign_get",
+            bucket.presign_get(path, 86400, queries.clone())
         )?;
-        let mut s = get_bytes_stream(url).await?;
-        while let Some(s) = StreamExt::next(&mut s).await {
-            match s {
-                Ok(b) => {
-                    stream.write_all(b.as_ref()).await?;
-                }
-                Err(e) => return Err(anyhow!(e)),
-            }
+        get_response(url).await
+    }
+
+    /// Counts mismatches per direction
+    pub fn verify(
+        &self,
+        direction: &str,
+        actual: &Checksums,
+        expected: &Checksums,
+    ) -> anyhow::Result<()> {
+        if let Err(e) = actual.verify(expected) {
+            METRICS
+                .checksum_failures
+                .with_label_values(&[&self.config.alias, direction])
+                .inc();
+            warn!("{direction} to {} failed: {e}", self.config.alias);
+            return Err(e.into());
         }
         Ok(())
     }
+
+    /// Checksums stored in object tags, empty if there are none or tagging isn't supported
+    pub async fn stored_checksums(&self, path: &str) -> Checksums {
+        match s3_with_timeout!(
+            self.config,
+            "get_object_tagging",
+            self.bucket().get_object_tagging(path)
+        ) {
+            Ok((tags, 200)) => Checksums::from_tags(&tags),
+            Ok(_) => Checksums::default(),
+            Err(e) => {
+                debug!("Cannot get tags of {path}: {e}");
+                Checksums::default()
+            }
+        }
+    }
+
+    /// Failing to store checksums doesn't fail the upload, they can be computed later.
+    /// PutObjectTagging replaces the whole set, so other tags are read and kept
+    pub async fn store_checksums(&self, path: &str, checksums: &Checksums) {
+        let ours = checksums.tags();
+        if ours.is_empty() {
+            return;
+        }
+        let mut tags: Vec<(String, String)> = match s3_with_timeout!(
+            self.config,
+            "get_object_tagging",
+            self.bucket().get_object_tagging(path)
+        ) {
+            Ok((tags, _)) => tags
+                .iter()
+                .map(|x| (x.key(), x.value()))
+                .filter(|(key, _)| key != SHA256_TAG && key != CRC32C_TAG)
+                .collect(),
+            Err(e) => {
+                warn!("Cannot store checksums of {path}, tags are not read: {e}");
+                return;
+            }
+        };
+        tags.extend(ours.into_iter().map(|(k, v)| (k.to_owned(), v)));
+        let tags: Vec<(&str, &str)> = tags.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        if let Err(e) = s3_with_timeout!(
+            self.config,
+            "put_object_tagging",
+            self.bucket().put_object_tagging(path, &tags)
+        ) {
+            warn!("Cannot store checksums of {path}: {e}");
+        }
+    }
+
+    /// Stored checksums, or computed by reading the object and stored then. True if computed
+    #[instrument(level = "trace", skip(self), fields(bucket = %self.config.alias))]
+    pub async fn checksums(&self, path: &str) -> anyhow::Result<(Checksums, bool)> {
+        let stored = self.stored_checksums(path).await;
+        if stored.sha256.is_some() && stored.crc32c.is_some() {
+            return Ok((stored, false));
+        }
+        let mut hasher = Hasher::new();
+        let mut s = self.get_object(path, None).await?.bytes_stream();
+        while let Some(b) = StreamExt::next(&mut s).await {
+            hasher.update(&b?);
+        }
+        let checksums = hasher.finish();
+        self.store_checksums(path, &checksums).await;
+        Ok((checksums, true))
+    }
+
     pub async fn mkdir(&self, path: &str) -> anyhow::Result<()> {
         info!("creating folder: {}", path);
         s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket.put_object(format!("{path}/.placeholder"), &[])
-        )
-        .map(|_| ())
+            self.config,
+            "put_object",
+            self.bucket()
+                .put_object(format!("{path}/.placeholder"), &[])
+        )?;
+        if let Some(index) = &self.index {
+            index.insert(&format!("{path}/.placeholder"), 0);
+        }
+        Ok(())
     }
     #[allow(unused)]
     pub async fn rename(&self) {}
@@ -364,56 +1459,354 @@ impl S3Client {
     pub async fn delete(&self, path: &str) -> anyhow::Result<()> {
         info!("Deleting {path}");
         s3_with_timeout!(
-            self.config.tries,
-            self.config.timeout,
-            self.bucket.delete_object(path)
-        )
-        .map(|_| ())
+            self.config,
+            "delete_object",
+            self.bucket().delete_object(path)
+        )?;
+        self.index_remove(&[path.to_owned()]);
+        Ok(())
     }
 
-    pub async fn delete_prefix(&self, path: &str) -> anyhow::Result<()> {
+    /// What `delete_prefix` would delete
+    pub async fn delete_preview(&self, path: &str) -> anyhow::Result<DeletePreview> {
+        let mut objects = self.list_all(path).await?;
+        objects.retain(|x| !in_trash(&x.key));
+        Ok(DeletePreview {
+            count: objects.len(),
+            size: objects.iter().map(|x| x.size).sum(),
+            sample: objects
+                .into_iter()
+                .take(PREVIEW_SAMPLE)
+                .map(|x| x.key)
+                .collect(),
+        })
+    }
+
+    /// Everything under `path`, placeholders and the folder object itself included, trash aside
+    pub async fn delete_prefix(
+        &self,
+        path: &str,
+        job: Option<&Job>,
+    ) -> anyhow::Result<DeleteReport> {
         info!("Deleting {path} (recursive)");
-        let mut data = vec![path.to_owned()];
-        let mut delete_list = vec![format!("{}.placeholder", path)];
-        let mut folder_delete_list = vec![path.to_owned()];
+        let keys: Vec<String> = self
+            .list_all(path)
+            .await?
+            .into_iter()
+            .map(|x| x.key)
+            .filter(|x| !in_trash(x))
+            .collect();
+        if let Some(job) = job {
+            job.set_total(keys.len() as u64);
+        }
+        self.delete_objects(&keys, job).await
+    }
 
-        loop {
-            let mut temp = Vec::new();
-            while let Some(folder) = data.pop() {
-                self.list(&folder)
-                    .await?
-                    .into_iter()
-                    .for_each(|x| match x.folder {
-                        true => {
-                            delete_list.push(format!("{}.placeholder", x.path));
-                            folder_delete_list.push(x.path.clone());
-                            temp.push(x.path);
-                        }
-                        false => {
-                            delete_list.push(x.path);
-                        }
-                    })
-            }
-            data.append(&mut temp);
-            if data.is_empty() {
-                break;
-            }
-        }
-        for deletion in delete_list {
-            s3_with_timeout!(
-                self.config.tries,
-                self.config.timeout,
-                self.bucket.delete_object(&deletion)
+    /// DeleteObjects in batches, keys S3 could not delete are reported and don't stop the rest.
+    /// A cancelled job stops between batches
+    pub async fn delete_objects(
+        &self,
+        keys: &[String],
+        job: Option<&Job>,
+    ) -> anyhow::Result<DeleteReport> {
+        let mut report = DeleteReport::default();
+        for batch in keys.chunks(DELETE_BATCH) {
+            if let Some(job) = job {
+                job.check()?;
+            }
+            let body = delete_request(batch);
+            let text = s3_with_timeout!(
+                self.config,
+                "delete_objects",
+                self.post("?delete", body.clone(), false)
             )?;
+            let result: DeleteResult = quick_xml::de::from_str(&text)?;
+            report.deleted += batch.len() - result.errors.len();
+            let failed: HashSet<&str> = result.errors.iter().map(|x| x.key.as_str()).collect();
+            let deleted: Vec<String> = batch
+                .iter()
+                .filter(|x| !failed.contains(x.as_str()))
+                .cloned()
+                .collect();
+            self.index_remove(&deleted);
+            for error in result.errors {
+                warn!(
+                    "Cannot delete {}: {} {}",
+                    error.key, error.code, error.message
+                );
+                report.failed.push(error.into());
+            }
+            if let Some(job) = job {
+                job.advance(batch.len() as u64);
+            }
         }
-        for deletion in folder_delete_list {
-            s3_with_timeout!(
-                self.config.tries,
-                self.config.timeout,
-                self.bucket.delete_object(&deletion)
-            )?;
+        Ok(report)
+    }
+
+    /// POSTs XML to `path_and_query` of the bucket, unless the object exists with `create_only`
+    async fn post(
+        &self,
+        path_and_query: &str,
+        body: String,
+        create_only: bool,
+    ) -> anyhow::Result<String> {
+        let mut headers = HeaderMap::new();
+        if create_only {
+            headers.insert("if-none-match", HeaderValue::from_static("*"));
         }
-        Ok(())
+        let md5 = STANDARD.encode(md5::compute(&body).0);
+        headers.insert("content-md5", HeaderValue::from_str(&md5)?);
+        headers.insert("content-type", HeaderValue::from_static("application/xml"));
+        self.send_signed(Method::POST, path_and_query, headers, body)
+            .await
+    }
+
+    /// Request to `path_and_query` of the bucket signed here, for the calls rust-s3 doesn't make.
+    /// Returns the response body
+    async fn send_signed(
+        &self,
+        method: Method,
+        path_and_query: &str,
+        headers: HeaderMap,
+        body: String,
+    ) -> anyhow::Result<String> {
+        let bucket = self.bucket();
+        let client = reqwest::ClientBuilder::new()
+            .danger_accept_invalid_certs(true)
+            .build()?;
+        let mut request = client
+            .request(method, format!("{}/{path_and_query}", bucket.url()))
+            .headers(headers)
+            .headers(telemetry::injected_headers())
+            .body(body)
+            .build()?;
+        let credentials = bucket.credentials().await?;
+        sigv4::sign(
+            &mut request,
+            &credentials,
+            &bucket.region().to_string(),
+            "s3",
+        )?;
+        let response = client.execute(request).await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if status == reqwest::StatusCode::PRECONDITION_FAILED {
+            return Err(PreconditionFailed.into());
+        }
+        if !status.is_success() {
+            bail!("S3 responded {status}: {text}");
+        }
+        Ok(text)
+    }
+}
+
+/// `<Delete>` body of DeleteObjects, only failures are returned in quiet mode
+fn delete_request(keys: &[String]) -> String {
+    let objects: String = keys
+        .iter()
+        .map(|x| format!("<Object><Key>{}</Key></Object>", escape(x.as_str())))
+        .collect();
+    format!("<Delete><Quiet>true</Quiet>{objects}</Delete>")
+}
+
+/// `?versions` query of ListObjectVersions, continuing after `marker` (key and version id)
+fn list_versions_query(prefix: &str, marker: Option<(String, String)>) -> String {
+    let mut query = format!(
+        "?versions&prefix={}&delimiter=%2F",
+        utf8_percent_encode(prefix, NON_ALPHANUMERIC)
+    );
+    if let Some((key, version_id)) = marker {
+        query = format!(
+            "{query}&key-marker={}&version-id-marker={}",
+            utf8_percent_encode(&key, NON_ALPHANUMERIC),
+            utf8_percent_encode(&version_id, NON_ALPHANUMERIC)
+        );
+    }
+    query
+}
+
+/// `<CompleteMultipartUpload>` body, with the part checksums if they were sent
+fn complete_request(parts: &[UploadedPart]) -> String {
+    let parts: String = parts
+        .iter()
+        .map(|x| {
+            let checksum = x
+                .crc32c
+                .as_ref()
+                .map(|x| format!("<ChecksumCRC32C>{x}</ChecksumCRC32C>"))
+                .unwrap_or_default();
+            format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag>{checksum}</Part>",
+                x.part_number,
+                escape(x.etag.as_str())
+            )
+        })
+        .collect();
+    format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>")
+}
+
+/// Credentials of the bucket, with its role assumed if configured
+async fn credentials(config: &S3Bucket) -> anyhow::Result<Credentials> {
+    let base = source_credentials(config).await?;
+    match &config.assume_role {
+        Some(role) => sts::assume_role(role, &base, config.timeout)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "bucket {}: cannot assume role {}: {e}",
+                    config.alias,
+                    role.role_arn
+                )
+            }),
+        None => Ok(base),
+    }
+}
+
+/// Credentials providers do blocking HTTP requests, so they run off the runtime threads
+async fn source_credentials(config: &S3Bucket) -> anyhow::Result<Credentials> {
+    let source = config.credentials.clone();
+    let (access_key, secret_key) = (config.access_key.clone(), config.secret_key.clone());
+    let profile = config.profile.clone();
+    let session_name = config.session_name.clone();
+    tokio::task::spawn_blocking(move || match source {
+        CredentialsSource::Static => {
+            Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)
+        }
+        CredentialsSource::Env => Credentials::from_env(),
+        CredentialsSource::Profile => Credentials::from_profile(profile.as_deref()),
+        CredentialsSource::WebIdentity => {
+            Credentials::from_sts_env(session_name.as_deref().unwrap_or("s3clix"))
+        }
+        CredentialsSource::InstanceMetadata => Credentials::from_instance_metadata_v2()
+            .or_else(|_| Credentials::from_instance_metadata()),
+        CredentialsSource::Default => Credentials::new(None, None, None, None, profile.as_deref()),
+    })
+    .await?
+    .map_err(|e| {
+        anyhow!(
+            "bucket {}: cannot get {:?} credentials: {e}",
+            config.alias,
+            config.credentials
+        )
+    })
+}
+
+/// When to refresh credentials, static ones never expire
+/// Seconds temporary credentials are valid for, `None` for long-term ones
+fn valid_for(creds: &Credentials) -> Option<u64> {
+    let expiration = SystemTime::from(creds.expiration?.0);
+    Some(
+        expiration
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+            .as_secs(),
+    )
+}
+
+fn refresh_in(creds: &Credentials) -> Option<Duration> {
+    Some(
+        Duration::from_secs(valid_for(creds)?)
+            .saturating_sub(Duration::from_secs(CREDENTIALS_MARGIN))
+            .max(Duration::from_secs(CREDENTIALS_RETRY)),
+    )
+}
+
+/// Uploads one part, retrying it `PART_TRIES` times on errors. Attempts borrow the data,
+/// so the part is in memory once
+async fn put_part(
+    config: Arc<S3Bucket>,
+    bucket: Arc<Bucket>,
+    path: String,
+    upload_id: String,
+    part_number: u32,
+    data: Bytes,
+) -> anyhow::Result<UploadedPart> {
+    let span = trace_span!(
+        "s3",
+        otel.name = "put_multipart_part",
+        part_number,
+        size = data.len()
+    );
+    let crc32c = config
+        .s3_checksums
+        .then(|| encode_crc32c(crc32c::crc32c(&data)));
+    async {
+        let mut attempt = 1;
+        loop {
+            let result = s3_with_timeout!(
+                config,
+                "put_multipart_chunk",
+                upload_part(
+                    &bucket,
+                    &path,
+                    &upload_id,
+                    part_number,
+                    &data,
+                    crc32c.as_deref()
+                )
+            );
+            match result {
+                Ok(part) => break Ok(part),
+                Err(e) if attempt < PART_TRIES => {
+                    warn!("Part {part_number} of {path} failed, attempt {attempt}: {e}");
+                    METRICS
+                        .s3_retries
+                        .with_label_values(&[&config.alias, "put_multipart_chunk"])
+                        .inc();
+                    tokio::time::sleep(Duration::from_secs(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => break Err(e),
+            }
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+/// UploadPart of borrowed data. rust-s3 `put_multipart_chunk` takes the data by value
+/// and aborts the whole upload when a part fails. rust-s3 adds `Content-MD5`
+async fn upload_part(
+    bucket: &Bucket,
+    path: &str,
+    upload_id: &str,
+    part_number: u32,
+    data: &[u8],
+    crc32c: Option<&str>,
+) -> anyhow::Result<UploadedPart> {
+    let with_checksum;
+    let bucket = match crc32c {
+        Some(crc32c) => {
+            let mut headers = bucket.extra_headers.clone();
+            headers.insert("x-amz-checksum-crc32c", HeaderValue::from_str(crc32c)?);
+            with_checksum = bucket.with_extra_headers(headers)?;
+            &with_checksum
+        }
+        None => bucket,
+    };
+    let command = Command::PutObject {
+        content: data,
+        content_type: mime::APPLICATION_OCTET_STREAM.as_ref(),
+        multipart: Some(Multipart::new(part_number, upload_id)),
+    };
+    let response = HyperRequest::new(bucket, path, command)
+        .await?
+        .response_data(true)
+        .await?;
+    Ok(UploadedPart {
+        etag: response.as_str()?.to_owned(),
+        part_number,
+        crc32c: crc32c.map(str::to_owned),
+    })
+}
+
+fn joined(
+    result: Option<Result<anyhow::Result<UploadedPart>, JoinError>>,
+) -> anyhow::Result<UploadedPart> {
+    match result {
+        Some(Ok(part)) => part,
+        Some(Err(e)) => Err(anyhow!(e)),
+        None => Err(anyhow!("no part upload is running")),
     }
 }
 
@@ -425,11 +1818,103 @@ fn strip_prefix(data: &str) -> String {
 }
 
 #[inline(always)]
-async fn get_bytes_stream(
-    url: String,
-) -> anyhow::Result<impl Stream<Item = Result<Bytes, reqwest::Error>>> {
+async fn get_response(url: String) -> anyhow::Result<reqwest::Response> {
     let client = reqwest::ClientBuilder::new()
         .danger_accept_invalid_certs(true)
         .build()?;
-    Ok(client.get(url).send().await?.bytes_stream())
+    let response = client
+        .get(url)
+        .headers(telemetry::injected_headers())
+        .send()
+        .await?;
+    // the error of error_for_status() would contain the presigned URL
+    if !response.status().is_success() {
+        bail!("S3 responded {}", response.status());
+    }
+    Ok(response)
+}
+
+/// ETag which is MD5 of the content: not multipart and not encrypted with KMS
+fn md5_etag(response: &reqwest::Response) -> Option<String> {
+    let headers = response.headers();
+    if headers
+        .get("x-amz-server-side-encryption")
+        .is_some_and(|x| x.as_bytes().starts_with(b"aws:kms"))
+        || headers.contains_key("x-amz-server-side-encryption-customer-algorithm")
+    {
+        return None;
+    }
+    let etag = headers.get("etag")?.to_str().ok()?.trim_matches('"');
+    match etag.len() == 32 && etag.chars().all(|x| x.is_ascii_hexdigit()) {
+        true => Some(etag.to_lowercase()),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        complete_request, list_versions_query, ListVersionsResult, UploadedPart, VersionEntry,
+    };
+
+    #[test]
+    fn test_list_versions_query() {
+        assert_eq!(
+            list_versions_query("a b/", None),
+            "?versions&prefix=a%20b%2F&delimiter=%2F"
+        );
+        assert_eq!(
+            list_versions_query("", Some(("a/b.txt".to_owned(), "v+1".to_owned()))),
+            "?versions&prefix=&delimiter=%2F&key-marker=a%2Fb%2Etxt&version-id-marker=v%2B1"
+        );
+    }
+
+    #[test]
+    fn test_complete_request() {
+        let parts = [
+            UploadedPart {
+                part_number: 1,
+                etag: "\"a\"".to_owned(),
+                crc32c: Some("yZRlqg==".to_owned()),
+            },
+            UploadedPart {
+                part_number: 2,
+                etag: "\"b\"".to_owned(),
+                crc32c: None,
+            },
+        ];
+        assert_eq!(
+            complete_request(&parts),
+            "<CompleteMultipartUpload>\
+            <Part><PartNumber>1</PartNumber><ETag>&quot;a&quot;</ETag><ChecksumCRC32C>yZRlqg==</ChecksumCRC32C></Part>\
+            <Part><PartNumber>2</PartNumber><ETag>&quot;b&quot;</ETag></Part>\
+            </CompleteMultipartUpload>"
+        );
+    }
+
+    #[test]
+    fn test_list_versions() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListVersionsResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name><Prefix>a/</Prefix><KeyMarker></KeyMarker>
+  <IsTruncated>true</IsTruncated>
+  <NextKeyMarker>a/b.txt</NextKeyMarker><NextVersionIdMarker>v1</NextVersionIdMarker>
+  <DeleteMarker><Key>a/b.txt</Key><VersionId>v3</VersionId><IsLatest>true</IsLatest>
+    <LastModified>2025-01-02T00:00:00.000Z</LastModified></DeleteMarker>
+  <Version><Key>a/b.txt</Key><VersionId>v2</VersionId><IsLatest>false</IsLatest>
+    <LastModified>2025-01-01T00:00:00.000Z</LastModified><ETag>"x"</ETag><Size>5</Size>
+    <StorageClass>STANDARD</StorageClass></Version>
+  <CommonPrefixes><Prefix>a/c/</Prefix></CommonPrefixes>
+</ListVersionsResult>"#;
+        let result: ListVersionsResult = quick_xml::de::from_str(xml).unwrap();
+        assert!(matches!(result.entries[3], VersionEntry::IsTruncated(true)));
+        let versions: Vec<_> = result
+            .entries
+            .into_iter()
+            .filter_map(VersionEntry::into_version)
+            .collect();
+        assert_eq!(versions.len(), 2);
+        assert!(versions[0].delete_marker && versions[0].latest);
+        assert_eq!((versions[1].size, versions[1].name.as_str()), (5, "b.txt"));
+    }
 }
@@ -0,0 +1,393 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use regex::{Regex, RegexBuilder};
+use s3::serde_types::CommonPrefix;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime, UtcOffset};
+
+use crate::index::MODIFIED_FORMAT;
+use crate::s3::FileList;
+use crate::trash::TRASH_PREFIX;
+
+/// Results returned when the query has no `limit`
+pub const SEARCH_LIMIT: usize = 1000;
+pub const SEARCH_MAX_LIMIT: usize = 10000;
+/// Set to `true` on answers cut at the limit
+pub const TRUNCATED_HEADER: &str = "x-search-truncated";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Part of the path, case-insensitive
+    #[default]
+    Substring,
+    /// `*`, `?`, `**` and `[...]`, case-insensitive. Matches the name, or the whole path if there is `/`
+    Glob,
+    /// Regular expression anywhere in the path, case-insensitive unless it starts with `(?-i)`
+    Regex,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SearchQuery {
+    /// Matches everything if empty
+    #[serde(default)]
+    pub pattern: String,
+    #[serde(default)]
+    pub mode: SearchMode,
+    /// Search under this folder only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// RFC 3339 time or a date as 2025-01-31
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_after: Option<String>,
+    /// RFC 3339 time or a date as 2025-01-31
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_before: Option<String>,
+    /// Return folders matching the pattern too
+    #[serde(default)]
+    pub folders: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+/// The query can't be run, answered with 400
+#[derive(Debug)]
+pub struct BadSearch(pub String);
+
+impl Display for BadSearch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BadSearch {}
+
+enum Matcher {
+    All,
+    Substring(AhoCorasick),
+    Name(Regex),
+    Path(Regex),
+}
+
+impl Matcher {
+    fn new(pattern: &str, mode: SearchMode) -> anyhow::Result<Self> {
+        if pattern.is_empty() {
+            return Ok(Self::All);
+        }
+        let bad = |e: regex::Error| BadSearch(format!("Bad pattern: {e}"));
+        Ok(match mode {
+            SearchMode::Substring => Self::Substring(
+                AhoCorasickBuilder::new()
+                    .ascii_case_insensitive(true)
+                    .build([pattern])?,
+            ),
+            SearchMode::Glob if pattern.contains('/') => {
+                Self::Path(glob_regex(pattern).map_err(bad)?)
+            }
+            SearchMode::Glob => Self::Name(glob_regex(pattern).map_err(bad)?),
+            SearchMode::Regex => Self::Path(
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(bad)?,
+            ),
+        })
+    }
+
+    /// `path` of a folder comes without the trailing `/`
+    fn is_match(&self, path: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Substring(ac) => ac.is_match(path),
+            Self::Name(re) => re.is_match(path.rsplit('/').next().unwrap_or_default()),
+            Self::Path(re) => re.is_match(path),
+        }
+    }
+}
+
+/// Translates the glob into an anchored regular expression, `*` and `?` stay within a folder
+fn glob_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                let rest: String = chars.clone().collect();
+                let Some(end) = rest.find(']').filter(|x| *x > 0) else {
+                    re.push_str(r"\[");
+                    continue;
+                };
+                let class = &rest[..end];
+                // past the class and `]`
+                chars.nth(class.chars().count());
+                re.push('[');
+                let class = match class.strip_prefix('!') {
+                    Some(negated) => {
+                        re.push('^');
+                        negated
+                    }
+                    None => class,
+                };
+                for x in class.chars() {
+                    if matches!(x, '\\' | '[' | '&' | '~' | '^') {
+                        re.push('\\');
+                    }
+                    re.push(x);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    RegexBuilder::new(&re).case_insensitive(true).build()
+}
+
+/// The time as S3 lists modification times, so they compare as strings
+fn modified_bound(value: &str) -> Result<String, BadSearch> {
+    let time = OffsetDateTime::parse(value, &Rfc3339)
+        .or_else(|_| {
+            Date::parse(value, format_description!("[year]-[month]-[day]"))
+                .map(|x| x.midnight().assume_utc())
+        })
+        .map_err(|_| BadSearch(format!("{value} is neither RFC 3339 time nor date")))?;
+    time.to_offset(UtcOffset::UTC)
+        .format(MODIFIED_FORMAT)
+        .map_err(|e| BadSearch(e.to_string()))
+}
+
+/// Collects the keys a query matches as they are listed or read from the index
+pub struct Search {
+    matcher: Matcher,
+    prefix: String,
+    min_size: u64,
+    max_size: u64,
+    after: Option<String>,
+    before: Option<String>,
+    /// Folders seen so far, if folders are searched
+    folders: Option<HashSet<String>>,
+    limit: usize,
+    pub files: Vec<FileList>,
+    pub truncated: bool,
+}
+
+impl Search {
+    pub fn new(query: &SearchQuery) -> anyhow::Result<Self> {
+        let limit = query.limit.unwrap_or(SEARCH_LIMIT);
+        if limit == 0 || limit > SEARCH_MAX_LIMIT {
+            return Err(BadSearch(format!("limit must be 1 to {SEARCH_MAX_LIMIT}")).into());
+        }
+        let mut prefix = query
+            .prefix
+            .as_deref()
+            .unwrap_or_default()
+            .trim_start_matches('/')
+            .to_owned();
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        Ok(Self {
+            matcher: Matcher::new(&query.pattern, query.mode)?,
+            prefix,
+            min_size: query.min_size.unwrap_or_default(),
+            max_size: query.max_size.unwrap_or(u64::MAX),
+            after: query
+                .modified_after
+                .as_deref()
+                .map(modified_bound)
+                .transpose()?,
+            before: query
+                .modified_before
+                .as_deref()
+                .map(modified_bound)
+                .transpose()?,
+            folders: query.folders.then(HashSet::new),
+            limit,
+            files: Vec::new(),
+            truncated: false,
+        })
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Adds the key, and with `folders` its folders, if they match.
+    /// False once the limit is exceeded and the rest can be skipped
+    pub fn offer(&mut self, key: &str, size: u64, last_modified: &str) -> bool {
+        if !key.starts_with(&self.prefix) || key.starts_with(TRASH_PREFIX) {
+            return true;
+        }
+        if let Some(seen) = &mut self.folders {
+            let mut matched = Vec::new();
+            for (i, _) in key[self.prefix.len()..].match_indices('/') {
+                let folder = &key[..=self.prefix.len() + i];
+                if seen.insert(folder.to_owned())
+                    && self.matcher.is_match(folder.trim_end_matches('/'))
+                {
+                    matched.push(folder.to_owned());
+                }
+            }
+            for folder in matched {
+                if !self.push(FileList::from(CommonPrefix { prefix: folder })) {
+                    return false;
+                }
+            }
+        }
+        // empty files are mostly folder placeholders
+        if size == 0 || size < self.min_size || size > self.max_size {
+            return true;
+        }
+        if self.after.as_deref().is_some_and(|x| last_modified < x)
+            || self.before.as_deref().is_some_and(|x| last_modified >= x)
+        {
+            return true;
+        }
+        if !self.matcher.is_match(key) {
+            return true;
+        }
+        self.push(FileList {
+            path: key.to_owned(),
+            name: key.rsplit('/').next().unwrap_or_default().to_owned(),
+            size,
+            folder: false,
+            cdn_url: None,
+        })
+    }
+
+    fn push(&mut self, file: FileList) -> bool {
+        if self.files.len() == self.limit {
+            self.truncated = true;
+            return false;
+        }
+        self.files.push(file);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn search(query: SearchQuery, keys: &[(&str, u64)]) -> (Vec<String>, bool) {
+        let mut search = Search::new(&query).unwrap();
+        for (key, size) in keys {
+            if !search.offer(key, *size, "2025-03-01T12:00:00.000Z") {
+                break;
+            }
+        }
+        let paths = search.files.into_iter().map(|x| x.path).collect();
+        (paths, search.truncated)
+    }
+
+    const KEYS: &[(&str, u64)] = &[
+        ("logs/2025/app.log", 10),
+        ("logs/2025/app.LOG.1", 20),
+        ("logs/2025/empty/.placeholder", 0),
+        ("logs/readme.txt", 30),
+        ("top.log", 40),
+    ];
+
+    #[test]
+    fn test_glob() {
+        let query = |pattern: &str| SearchQuery {
+            pattern: pattern.to_owned(),
+            mode: SearchMode::Glob,
+            ..Default::default()
+        };
+        assert_eq!(
+            search(query("*.log"), KEYS).0,
+            vec!["logs/2025/app.log", "top.log"]
+        );
+        assert_eq!(
+            search(query("logs/**/*.log*"), KEYS).0,
+            vec!["logs/2025/app.log", "logs/2025/app.LOG.1"]
+        );
+        assert_eq!(search(query("[!a-s]*"), KEYS).0, vec!["top.log"]);
+        assert_eq!(search(query("logs/*.txt"), KEYS).0, vec!["logs/readme.txt"]);
+    }
+
+    #[test]
+    fn test_filters() {
+        let query = SearchQuery {
+            prefix: Some("logs".to_owned()),
+            min_size: Some(15),
+            folders: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            search(query, KEYS).0,
+            vec![
+                "logs/2025/",
+                "logs/2025/app.LOG.1",
+                "logs/2025/empty/",
+                "logs/readme.txt"
+            ]
+        );
+        let query = SearchQuery {
+            pattern: "log$".to_owned(),
+            mode: SearchMode::Regex,
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            search(query, KEYS),
+            (vec!["logs/2025/app.log".to_owned()], true)
+        );
+        let query = |pattern: &str| SearchQuery {
+            pattern: pattern.to_owned(),
+            mode: SearchMode::Regex,
+            ..Default::default()
+        };
+        assert_eq!(
+            search(query(r"LOG\.\d$"), KEYS).0,
+            vec!["logs/2025/app.LOG.1"]
+        );
+        assert!(search(query(r"(?-i)log\.\d$"), KEYS).0.is_empty());
+        let query = SearchQuery {
+            modified_before: Some("2025-03-01".to_owned()),
+            ..Default::default()
+        };
+        assert!(search(query, KEYS).0.is_empty());
+        let query = SearchQuery {
+            modified_after: Some("2025-03-01T13:00:00+02:00".to_owned()),
+            max_size: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(search(query, KEYS).0, vec!["logs/2025/app.log"]);
+    }
+}
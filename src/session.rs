@@ -0,0 +1,260 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rand::RngCore;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::checksum::{encode_crc32c, Checksums};
+use crate::config::OverwritePolicy;
+use crate::s3::{S3Client, UploadedPart};
+
+/// S3 allows part numbers from 1 to 10000
+pub const MAX_PART_NUMBER: u32 = 10000;
+/// Expired sessions are looked for at most this often, seconds
+const CLEANUP_INTERVAL: u64 = 600;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SessionPart {
+    pub part_number: u32,
+    pub size: u64,
+    pub etag: String,
+    pub sha256: String,
+    pub crc32c: String,
+    /// to combine into CRC32C of the whole object
+    #[serde(skip)]
+    pub crc: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionStatus {
+    pub id: String,
+    pub bucket: String,
+    pub path: String,
+    /// total size of the uploaded parts
+    pub size: u64,
+    pub parts: Vec<SessionPart>,
+}
+
+/// Resumable upload: an S3 multipart upload plus the parts uploaded so far
+pub struct UploadSession {
+    pub client: Arc<S3Client>,
+    pub path: String,
+    pub upload_id: String,
+    /// who started the upload, only they can continue it
+    pub user: String,
+    /// checked again when the upload is completed
+    pub policy: OverwritePolicy,
+    parts: BTreeMap<u32, SessionPart>,
+    touched: Instant,
+}
+
+impl UploadSession {
+    fn belongs(&self, bucket: &str, user: &str) -> bool {
+        self.client.config.alias == bucket && self.user == user
+    }
+
+    fn status(&self, id: &str) -> SessionStatus {
+        SessionStatus {
+            id: id.to_owned(),
+            bucket: self.client.config.alias.clone(),
+            path: self.path.clone(),
+            size: self.size(),
+            parts: self.parts.values().cloned().collect(),
+        }
+    }
+
+    /// Bytes of the uploaded parts
+    pub fn size(&self) -> u64 {
+        self.parts.values().map(|x| x.size).sum()
+    }
+
+    /// CRC32C of the object the parts make, SHA-256 cannot be combined from parts
+    pub fn checksums(&self) -> Checksums {
+        let crc = self.parts.values().fold(0, |crc, x| {
+            crc32c::crc32c_combine(crc, x.crc, x.size as usize)
+        });
+        Checksums {
+            sha256: None,
+            crc32c: Some(encode_crc32c(crc)),
+        }
+    }
+
+    pub fn parts(&self) -> Vec<UploadedPart> {
+        self.parts
+            .values()
+            .map(|x| UploadedPart {
+                part_number: x.part_number,
+                etag: x.etag.clone(),
+                crc32c: self.client.config.s3_checksums.then(|| x.crc32c.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Upload sessions by id. They live in memory only: after restart clients start over,
+/// unfinished multipart uploads are left to the bucket lifecycle policy
+pub struct UploadSessions {
+    sessions: Mutex<HashMap<String, UploadSession>>,
+    ttl: AtomicU64,
+}
+
+impl UploadSessions {
+    pub fn new(ttl: u64) -> Arc<Self> {
+        let sessions = Arc::new(Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl: AtomicU64::new(ttl),
+        });
+        sessions.spawn_cleanup();
+        sessions
+    }
+
+    pub fn set_ttl(&self, ttl: u64) {
+        self.ttl.store(ttl, Ordering::Relaxed);
+    }
+
+    /// Starts multipart upload of `path` for `user`
+    pub async fn create(
+        &self,
+        client: Arc<S3Client>,
+        path: &str,
+        user: &str,
+        policy: OverwritePolicy,
+    ) -> anyhow::Result<SessionStatus> {
+        let upload_id = client.start_upload(path, true).await?;
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let id = hex::encode(bytes);
+        info!(
+            "Upload session {id} for {path} in {} started",
+            client.config.alias
+        );
+        let session = UploadSession {
+            client,
+            path: path.to_owned(),
+            upload_id,
+            user: user.to_owned(),
+            policy,
+            parts: BTreeMap::new(),
+            touched: Instant::now(),
+        };
+        let status = session.status(&id);
+        self.sessions.lock().await.insert(id, session);
+        Ok(status)
+    }
+
+    /// Session status, sessions of other buckets and users are not visible
+    pub async fn status(&self, id: &str, bucket: &str, user: &str) -> Option<SessionStatus> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(id).filter(|x| x.belongs(bucket, user))?;
+        session.touched = Instant::now();
+        Some(session.status(id))
+    }
+
+    /// Client, path and upload id to upload a part with, the lock is not held meanwhile
+    pub async fn target(
+        &self,
+        id: &str,
+        bucket: &str,
+        user: &str,
+    ) -> Option<(Arc<S3Client>, String, String)> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(id).filter(|x| x.belongs(bucket, user))?;
+        session.touched = Instant::now();
+        Some((
+            session.client.clone(),
+            session.path.clone(),
+            session.upload_id.clone(),
+        ))
+    }
+
+    /// Records uploaded part, an upload of the same number replaces it
+    pub async fn add_part(
+        &self,
+        id: &str,
+        bucket: &str,
+        user: &str,
+        part: SessionPart,
+    ) -> Option<SessionStatus> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(id).filter(|x| x.belongs(bucket, user))?;
+        session.touched = Instant::now();
+        session.parts.insert(part.part_number, part);
+        Some(session.status(id))
+    }
+
+    /// Removes session to complete or abort it
+    pub async fn take(&self, id: &str, bucket: &str, user: &str) -> Option<UploadSession> {
+        let mut sessions = self.sessions.lock().await;
+        if !sessions.get(id)?.belongs(bucket, user) {
+            return None;
+        }
+        sessions.remove(id)
+    }
+
+    /// Puts session back, e.g. when completion failed and the client may retry
+    pub async fn restore(&self, id: &str, mut session: UploadSession) {
+        session.touched = Instant::now();
+        self.sessions.lock().await.insert(id.to_owned(), session);
+    }
+
+    async fn expire(&self) {
+        let ttl = Duration::from_secs(self.ttl.load(Ordering::Relaxed));
+        let expired: Vec<(String, UploadSession)> = {
+            let mut sessions = self.sessions.lock().await;
+            let ids: Vec<String> = sessions
+                .iter()
+                .filter(|(_, x)| x.touched.elapsed() > ttl)
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| sessions.remove(&id).map(|x| (id, x)))
+                .collect()
+        };
+        for (id, session) in expired {
+            info!("Upload session {id} for {} expired", session.path);
+            if let Err(e) = session
+                .client
+                .abort_upload(&session.path, &session.upload_id)
+                .await
+            {
+                warn!("Cannot abort expired upload session {id}: {e}");
+            }
+        }
+    }
+
+    fn spawn_cleanup(self: &Arc<Self>) {
+        let sessions: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                let sleep = match sessions.upgrade() {
+                    None => break,
+                    Some(x) => x.ttl.load(Ordering::Relaxed).clamp(1, CLEANUP_INTERVAL),
+                };
+                tokio::time::sleep(Duration::from_secs(sleep)).await;
+                let Some(sessions) = sessions.upgrade() else {
+                    break;
+                };
+                sessions.expire().await;
+            }
+        });
+    }
+}
@@ -0,0 +1,664 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::Mutex;
+
+use crate::config::constant_time_eq;
+use crate::health::unix_now;
+
+/// Expired shares are looked for at most this often, seconds
+const CLEANUP_INTERVAL: u64 = 600;
+/// PBKDF2-HMAC-SHA256 iterations for share passwords, as OWASP recommends
+const PASSWORD_ROUNDS: u32 = 600_000;
+/// Wrong passwords an address may send before it has to wait between attempts
+const FREE_PASSWORD_FAILURES: u32 = 5;
+/// Longest wait between attempts, also how long wrong passwords are remembered
+const MAX_PASSWORD_DELAY: Duration = Duration::from_secs(900);
+
+#[derive(Debug)]
+pub struct ShareNotFound;
+
+impl Display for ShareNotFound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Link not found")
+    }
+}
+
+impl std::error::Error for ShareNotFound {}
+
+/// The link expired or its downloads are used up
+#[derive(Debug)]
+pub struct ShareGone;
+
+impl Display for ShareGone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Link has expired")
+    }
+}
+
+impl std::error::Error for ShareGone {}
+
+#[derive(Debug)]
+pub struct SharePassword;
+
+impl Display for SharePassword {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Share link needs password")
+    }
+}
+
+impl std::error::Error for SharePassword {}
+
+/// Too many wrong passwords from the address, seconds to wait
+#[derive(Debug)]
+pub struct ShareThrottled(pub u64);
+
+impl Display for ShareThrottled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Too many wrong passwords, try again in {} seconds",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ShareThrottled {}
+
+/// Upload above the limits of a file request
+#[derive(Debug)]
+pub struct RequestLimit(pub String);
+
+impl Display for RequestLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RequestLimit {}
+
+/// File given out by a public link
+pub struct Share {
+    pub token: String,
+    pub bucket: String,
+    pub path: String,
+    /// who created the link
+    pub user: String,
+    created: u64,
+    expires: u64,
+    password: Option<PasswordHash>,
+    /// wrong passwords and the checks running, by client address
+    failures: std::sync::Mutex<HashMap<IpAddr, Failures>>,
+    max_downloads: Option<u64>,
+    downloads: AtomicU64,
+}
+
+/// Wrong passwords from one address
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Failures {
+    /// How long the address waits before its next attempt: nothing for the
+    /// first `FREE_PASSWORD_FAILURES`, then doubling up to `MAX_PASSWORD_DELAY`
+    fn wait(&self) -> Duration {
+        let Some(over) = self.count.checked_sub(FREE_PASSWORD_FAILURES) else {
+            return Duration::ZERO;
+        };
+        let delay = Duration::from_secs(1 << over.min(10)).min(MAX_PASSWORD_DELAY);
+        delay.saturating_sub(self.last.elapsed())
+    }
+}
+
+struct PasswordHash {
+    salt: [u8; 16],
+    rounds: u32,
+    hash: [u8; 32],
+}
+
+impl PasswordHash {
+    fn new(password: &str, rounds: u32) -> Self {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self {
+            salt,
+            rounds,
+            hash: pbkdf2(password.as_bytes(), &salt, rounds),
+        }
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        constant_time_eq(
+            &pbkdf2(password.as_bytes(), &self.salt, self.rounds),
+            &self.hash,
+        )
+    }
+}
+
+/// PBKDF2-HMAC-SHA256 of one block, the size of the hash
+fn pbkdf2(password: &[u8], salt: &[u8], rounds: u32) -> [u8; 32] {
+    let mac = Hmac::<Sha256>::new_from_slice(password).expect("HMAC takes keys of any size");
+    let mut block = mac
+        .clone()
+        .chain_update(salt)
+        .chain_update(1u32.to_be_bytes())
+        .finalize()
+        .into_bytes();
+    let mut hash: [u8; 32] = block.into();
+    for _ in 1..rounds {
+        block = mac.clone().chain_update(block).finalize().into_bytes();
+        hash.iter_mut().zip(block).for_each(|(x, y)| *x ^= y);
+    }
+    hash
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ShareInfo {
+    pub token: String,
+    pub bucket: String,
+    pub path: String,
+    pub user: String,
+    pub created: u64,
+    pub expires: u64,
+    /// whether the link needs password
+    pub password: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    pub downloads: u64,
+}
+
+impl Share {
+    pub fn info(&self) -> ShareInfo {
+        ShareInfo {
+            token: self.token.clone(),
+            bucket: self.bucket.clone(),
+            path: self.path.clone(),
+            user: self.user.clone(),
+            created: self.created,
+            expires: self.expires,
+            password: self.password.is_some(),
+            max_downloads: self.max_downloads,
+            downloads: self.downloads.load(Ordering::Relaxed),
+        }
+    }
+
+    fn used_up(&self) -> bool {
+        self.max_downloads
+            .is_some_and(|x| self.downloads.load(Ordering::Relaxed) >= x)
+    }
+
+    fn expired(&self) -> bool {
+        self.expires <= unix_now() || self.used_up()
+    }
+
+    /// Counts a download, fails with `ShareGone` if there are none left
+    pub fn count_download(&self) -> anyhow::Result<()> {
+        let limit = self.max_downloads.unwrap_or(u64::MAX);
+        self.downloads
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                (x < limit).then_some(x + 1)
+            })
+            .map_err(|_| ShareGone)?;
+        Ok(())
+    }
+}
+
+/// Upload-only link: anyone with it can add files under `prefix`, nothing else
+pub struct FileRequest {
+    pub token: String,
+    pub bucket: String,
+    pub prefix: String,
+    /// who created the link, uploads are logged against them
+    pub user: String,
+    created: u64,
+    expires: u64,
+    pub max_file_size: Option<u64>,
+    pub max_total_size: u64,
+    /// bytes uploaded or being uploaded
+    uploaded: AtomicU64,
+    files: AtomicU64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileRequestInfo {
+    pub token: String,
+    pub bucket: String,
+    pub prefix: String,
+    pub user: String,
+    pub created: u64,
+    pub expires: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    pub max_total_size: u64,
+    pub uploaded: u64,
+    pub files: u64,
+}
+
+impl FileRequest {
+    pub fn info(&self) -> FileRequestInfo {
+        FileRequestInfo {
+            token: self.token.clone(),
+            bucket: self.bucket.clone(),
+            prefix: self.prefix.clone(),
+            user: self.user.clone(),
+            created: self.created,
+            expires: self.expires,
+            max_file_size: self.max_file_size,
+            max_total_size: self.max_total_size,
+            uploaded: self.uploaded.load(Ordering::Relaxed),
+            files: self.files.load(Ordering::Relaxed),
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.expires <= unix_now()
+    }
+
+    /// Takes `size` bytes of the limits before upload, fails with `RequestLimit`
+    pub fn reserve(&self, size: u64) -> anyhow::Result<()> {
+        if let Some(max) = self.max_file_size.filter(|x| size > *x) {
+            return Err(RequestLimit(format!("Files up to {max} bytes are accepted")).into());
+        }
+        let limit = self.max_total_size;
+        self.uploaded
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                x.checked_add(size).filter(|x| *x <= limit)
+            })
+            .map_err(|x| RequestLimit(format!("{} bytes are left", limit.saturating_sub(x))))?;
+        Ok(())
+    }
+
+    /// Returns the bytes of a failed upload
+    pub fn release(&self, size: u64) {
+        self.uploaded.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    pub fn uploaded(&self) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn token() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Share links and file requests by token. They live in memory of this process only:
+/// a restart revokes them and other replicas don't know them, so their validity is capped
+pub struct Shares {
+    shares: Mutex<HashMap<String, Arc<Share>>>,
+    requests: Mutex<HashMap<String, Arc<FileRequest>>>,
+    password_rounds: u32,
+}
+
+impl Shares {
+    pub fn new() -> Arc<Self> {
+        Self::with_rounds(PASSWORD_ROUNDS)
+    }
+
+    fn with_rounds(password_rounds: u32) -> Arc<Self> {
+        let shares = Arc::new(Self {
+            shares: Mutex::new(HashMap::new()),
+            requests: Mutex::new(HashMap::new()),
+            password_rounds,
+        });
+        shares.spawn_cleanup();
+        shares
+    }
+
+    /// New link to `path` valid for `ttl` seconds
+    pub async fn create(
+        &self,
+        bucket: &str,
+        path: &str,
+        user: &str,
+        ttl: u64,
+        password: Option<&str>,
+        max_downloads: Option<u64>,
+    ) -> anyhow::Result<ShareInfo> {
+        let token = token();
+        let password = match password {
+            Some(password) => {
+                let (password, rounds) = (password.to_owned(), self.password_rounds);
+                // the hash is slow on purpose, off the runtime threads
+                Some(
+                    tokio::task::spawn_blocking(move || PasswordHash::new(&password, rounds))
+                        .await?,
+                )
+            }
+            None => None,
+        };
+        let now = unix_now();
+        let share = Arc::new(Share {
+            token: token.clone(),
+            bucket: bucket.to_owned(),
+            path: path.to_owned(),
+            user: user.to_owned(),
+            created: now,
+            expires: now + ttl,
+            password,
+            failures: Default::default(),
+            max_downloads,
+            downloads: AtomicU64::new(0),
+        });
+        info!("Share link for {path} in {bucket} created by {user}, valid for {ttl}s");
+        let info = share.info();
+        self.shares.lock().await.insert(token, share);
+        Ok(info)
+    }
+
+    /// Link which can be downloaded, the password is checked if it has one.
+    /// Wrong passwords slow down further attempts from the same `client` only,
+    /// so a guesser can't lock out the other recipients of the link
+    pub async fn get(
+        &self,
+        token: &str,
+        password: Option<&str>,
+        client: IpAddr,
+    ) -> anyhow::Result<Arc<Share>> {
+        let share = self
+            .shares
+            .lock()
+            .await
+            .get(token)
+            .cloned()
+            .ok_or(ShareNotFound)?;
+        if share.expired() {
+            return Err(ShareGone.into());
+        }
+        if share.password.is_none() {
+            return Ok(share);
+        }
+        let Some(password) = password else {
+            return Err(SharePassword.into());
+        };
+        // the attempt is counted before the check, so parallel guesses wait as well
+        {
+            let mut failures = share.failures.lock().unwrap();
+            failures.retain(|_, x| x.last.elapsed() < MAX_PASSWORD_DELAY);
+            let failure = failures.entry(client).or_insert(Failures {
+                count: 0,
+                last: Instant::now(),
+            });
+            let wait = failure.wait();
+            if !wait.is_zero() {
+                return Err(ShareThrottled(wait.as_secs().max(1)).into());
+            }
+            failure.count += 1;
+            failure.last = Instant::now();
+        }
+        let (checked, password) = (share.clone(), password.to_owned());
+        let valid = tokio::task::spawn_blocking(move || {
+            checked
+                .password
+                .as_ref()
+                .is_some_and(|x| x.verify(&password))
+        })
+        .await?;
+        if !valid {
+            warn!(
+                "Wrong password for share link of {} in {} from {client}",
+                share.path, share.bucket
+            );
+            return Err(SharePassword.into());
+        }
+        share.failures.lock().unwrap().remove(&client);
+        Ok(share)
+    }
+
+    /// Links of the user, or all of them for `None`, newest first
+    pub async fn list(&self, user: Option<&str>) -> Vec<ShareInfo> {
+        let mut shares: Vec<ShareInfo> = self
+            .shares
+            .lock()
+            .await
+            .values()
+            .filter(|x| user.is_none_or(|user| x.user == user))
+            .map(|x| x.info())
+            .collect();
+        shares.sort_by_key(|x| std::cmp::Reverse(x.created));
+        shares
+    }
+
+    /// Removes the link of the user, or anyone's for `None`
+    pub async fn revoke(&self, token: &str, user: Option<&str>) -> bool {
+        let mut shares = self.shares.lock().await;
+        match shares.get(token) {
+            Some(share) if user.is_none_or(|user| share.user == user) => {
+                info!("Share link for {} in {} revoked", share.path, share.bucket);
+                shares.remove(token);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// New upload-only link to the `prefix` folder valid for `ttl` seconds
+    pub async fn create_request(
+        &self,
+        bucket: &str,
+        prefix: &str,
+        user: &str,
+        ttl: u64,
+        max_file_size: Option<u64>,
+        max_total_size: u64,
+    ) -> FileRequestInfo {
+        let token = token();
+        let now = unix_now();
+        let request = Arc::new(FileRequest {
+            token: token.clone(),
+            bucket: bucket.to_owned(),
+            prefix: prefix.to_owned(),
+            user: user.to_owned(),
+            created: now,
+            expires: now + ttl,
+            max_file_size,
+            max_total_size,
+            uploaded: AtomicU64::new(0),
+            files: AtomicU64::new(0),
+        });
+        info!("File request for {prefix} in {bucket} created by {user}, valid for {ttl}s");
+        let info = request.info();
+        self.requests.lock().await.insert(token, request);
+        info
+    }
+
+    /// File request which accepts uploads
+    pub async fn get_request(&self, token: &str) -> anyhow::Result<Arc<FileRequest>> {
+        let request = self
+            .requests
+            .lock()
+            .await
+            .get(token)
+            .cloned()
+            .ok_or(ShareNotFound)?;
+        match request.expired() {
+            true => Err(ShareGone.into()),
+            false => Ok(request),
+        }
+    }
+
+    /// File requests of the user, or all of them for `None`, newest first
+    pub async fn list_requests(&self, user: Option<&str>) -> Vec<FileRequestInfo> {
+        let mut requests: Vec<FileRequestInfo> = self
+            .requests
+            .lock()
+            .await
+            .values()
+            .filter(|x| user.is_none_or(|user| x.user == user))
+            .map(|x| x.info())
+            .collect();
+        requests.sort_by_key(|x| std::cmp::Reverse(x.created));
+        requests
+    }
+
+    /// Removes the file request of the user, or anyone's for `None`
+    pub async fn revoke_request(&self, token: &str, user: Option<&str>) -> bool {
+        let mut requests = self.requests.lock().await;
+        match requests.get(token) {
+            Some(request) if user.is_none_or(|user| request.user == user) => {
+                info!(
+                    "File request for {} in {} revoked",
+                    request.prefix, request.bucket
+                );
+                requests.remove(token);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn spawn_cleanup(self: &Arc<Self>) {
+        let shares: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(CLEANUP_INTERVAL)).await;
+                let Some(shares) = shares.upgrade() else {
+                    break;
+                };
+                shares.shares.lock().await.retain(|_, x| !x.expired());
+                shares.requests.lock().await.retain(|_, x| !x.expired());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pbkdf2() {
+        // RFC 7914 and common PBKDF2-HMAC-SHA256 test vectors
+        for (rounds, expected) in [
+            (
+                1,
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+            ),
+            (
+                2,
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43",
+            ),
+            (
+                4096,
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a",
+            ),
+        ] {
+            assert_eq!(hex::encode(pbkdf2(b"password", b"salt", rounds)), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let shares = Shares::with_rounds(1);
+        let info = shares
+            .create("bucket", "a.txt", "alice", 60, Some("secret"), None)
+            .await
+            .unwrap();
+        let (guesser, recipient) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        assert!(shares
+            .get(&info.token, Some("secret"), guesser)
+            .await
+            .is_ok());
+        for _ in 0..FREE_PASSWORD_FAILURES {
+            let e = shares
+                .get(&info.token, Some("wrong"), guesser)
+                .await
+                .err()
+                .unwrap();
+            assert!(e.is::<SharePassword>());
+        }
+        let e = shares
+            .get(&info.token, Some("secret"), guesser)
+            .await
+            .err()
+            .unwrap();
+        assert!(e.is::<ShareThrottled>());
+        assert!(shares
+            .get(&info.token, Some("secret"), recipient)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_share() {
+        let shares = Shares::with_rounds(1);
+        let client = [127, 0, 0, 1].into();
+        let info = shares
+            .create("bucket", "a.txt", "alice", 60, Some("secret"), Some(1))
+            .await
+            .unwrap();
+        assert!(info.password);
+        assert!(shares.get(&info.token, None, client).await.is_err());
+        assert!(shares
+            .get(&info.token, Some("wrong"), client)
+            .await
+            .is_err());
+        let share = shares
+            .get(&info.token, Some("secret"), client)
+            .await
+            .unwrap();
+        assert!(share.count_download().is_ok());
+        assert!(share.count_download().is_err());
+        let e = shares
+            .get(&info.token, Some("secret"), client)
+            .await
+            .err()
+            .unwrap();
+        assert!(e.is::<ShareGone>());
+
+        let info = shares
+            .create("bucket", "b.txt", "alice", 60, None, None)
+            .await
+            .unwrap();
+        assert!(shares.get(&info.token, None, client).await.is_ok());
+        assert_eq!(shares.list(Some("bob")).await.len(), 0);
+        assert!(!shares.revoke(&info.token, Some("bob")).await);
+        assert!(shares.revoke(&info.token, None).await);
+        let e = shares.get(&info.token, None, client).await.err().unwrap();
+        assert!(e.is::<ShareNotFound>());
+    }
+
+    #[tokio::test]
+    async fn test_request_limits() {
+        let shares = Shares::new();
+        let info = shares
+            .create_request("bucket", "in/", "alice", 60, Some(10), 15)
+            .await;
+        let request = shares.get_request(&info.token).await.unwrap();
+        assert!(request.reserve(11).is_err());
+        assert!(request.reserve(10).is_ok());
+        assert!(request.reserve(6).is_err());
+        request.release(10);
+        assert!(request.reserve(6).is_ok());
+        assert!(request.reserve(9).is_ok());
+        assert!(request.reserve(1).err().unwrap().is::<RequestLimit>());
+        assert_eq!(request.info().uploaded, 15);
+    }
+}
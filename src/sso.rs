@@ -14,6 +14,8 @@ See the License for the specific language governing permissions and
 limitations under the License.
 **/
 use crate::config::Config;
+use crate::metrics::METRICS;
+use crate::telemetry;
 use anyhow::{anyhow, bail};
 use base64::Engine;
 use jwt_simple::common::VerificationOptions;
@@ -24,7 +26,9 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::RwLock;
+use tracing::instrument;
 
 #[derive(Debug, Deserialize)]
 pub struct WellKnownConfiguration {
@@ -54,13 +58,17 @@ pub struct Jwks {
     pub keys: Vec<JWKey>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct SSOAuthConfig {
     /** server configuration **/
     pub redirect: String,
     pub resource: String,
     pub client_id: String,
+    #[serde(default)]
     pub secret: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub secret_file: Option<String>,
     #[serde(default = "default_username")]
     pub username_claim: String,
     #[serde(default = "default_groups")]
@@ -101,6 +109,7 @@ impl Default for SSOAuthConfig {
             resource: "".to_string(),
             client_id: "".to_string(),
             secret: "".to_string(),
+            secret_file: None,
             username_claim: "username".to_string(),
             groups_claim: "groups".to_string(),
             scope: "openid".to_string(),
@@ -119,6 +128,18 @@ pub struct SSOConfig {
     token_endpoint: String,
     authorize_endpoint: String,
     jwks_endpoint: String,
+    refreshed_at: Option<SystemTime>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SSOStatus {
+    pub ready: bool,
+    pub keys: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwks_age_seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -164,7 +185,7 @@ pub struct TokenResponse {
 }
 
 #[derive(Debug)]
-enum Action {
+pub enum Action {
     View,
     Delete,
     Upload,
@@ -172,12 +193,46 @@ enum Action {
 
 impl SSOConfig {
     pub fn ready(&self) -> bool {
-        self.keys.len() > 0
-            && !(self.token_endpoint.is_empty() || self.authorize_endpoint.is_empty())
+        !(self.keys.is_empty()
+            || self.token_endpoint.is_empty()
+            || self.authorize_endpoint.is_empty())
     }
     pub async fn update(sso_auth: Arc<RwLock<SSOConfig>>) -> anyhow::Result<()> {
+        let result = Self::fetch(sso_auth.clone()).await;
+        let mut sso_auth = sso_auth.write().await;
+        let label = match &result {
+            Ok(_) => {
+                sso_auth.refreshed_at = Some(SystemTime::now());
+                sso_auth.last_error = None;
+                "success"
+            }
+            Err(e) => {
+                sso_auth.last_error = Some(e.to_string());
+                "failure"
+            }
+        };
+        METRICS.sso_refresh.with_label_values(&[label]).inc();
+        result
+    }
+    pub fn status(&self) -> SSOStatus {
+        SSOStatus {
+            ready: self.ready(),
+            keys: self.keys.len(),
+            jwks_age_seconds: self
+                .refreshed_at
+                .and_then(|x| x.elapsed().ok())
+                .map(|x| x.as_secs()),
+            last_error: self.last_error.clone(),
+        }
+    }
+    #[instrument(level = "trace", name = "sso_refresh", skip_all)]
+    async fn fetch(sso_auth: Arc<RwLock<SSOConfig>>) -> anyhow::Result<()> {
         let mut sso_auth = sso_auth.write().await;
-        let well_known: WellKnownConfiguration = reqwest::get(&sso_auth.config.well_known)
+        let cli = reqwest::Client::new();
+        let well_known: WellKnownConfiguration = cli
+            .get(&sso_auth.config.well_known)
+            .headers(telemetry::injected_headers())
+            .send()
             .await?
             .json()
             .await?;
@@ -186,7 +241,13 @@ impl SSOConfig {
         sso_auth.jwks_endpoint = well_known.jwks_uri;
         sso_auth.keys.clear();
         debug!("Loaded endpoints from SSO well-known configuration");
-        let jwks: Jwks = reqwest::get(&sso_auth.jwks_endpoint).await?.json().await?;
+        let jwks: Jwks = cli
+            .get(&sso_auth.jwks_endpoint)
+            .headers(telemetry::injected_headers())
+            .send()
+            .await?
+            .json()
+            .await?;
         debug!("Loaded JWKS with {} keys", jwks.keys.len());
         for key in jwks.keys {
             let e = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(&key.e)?;
@@ -202,6 +263,9 @@ impl SSOConfig {
         }
         Ok(())
     }
+    pub fn auth_config(&self) -> &SSOAuthConfig {
+        &self.config
+    }
     pub fn get_cookie_name(&self) -> &str {
         self.config.cookie_name.as_str()
     }
@@ -222,6 +286,7 @@ impl SSOConfig {
         if let Some(last_err) = last_err {
             warn!("Verification failed: {last_err}");
         }
+        METRICS.sso_verify_failures.inc();
         Err(anyhow!("Verification failed"))
     }
     pub fn build_redirect_url(&self) -> anyhow::Result<Url> {
@@ -236,6 +301,7 @@ impl SSOConfig {
         Ok(url)
     }
 
+    #[instrument(level = "trace", skip_all)]
     pub async fn code_exchange(&self, code: &RedirectCode) -> anyhow::Result<TokenResponse> {
         let cli = reqwest::Client::new();
         let mut query: HashMap<&str, &str> = HashMap::new();
@@ -246,6 +312,7 @@ impl SSOConfig {
         query.insert("grant_type", "authorization_code");
         Ok(cli
             .post(&self.token_endpoint)
+            .headers(telemetry::injected_headers())
             .form(&query)
             .send()
             .await?
@@ -293,6 +360,17 @@ impl SSOConfig {
                 return false;
             }
         };
+        self.groups_can(action, global_config, &groups, bucket)
+    }
+
+    /// Same group rules as for SSO users, API tokens have their groups in configuration
+    pub fn groups_can(
+        &self,
+        action: Action,
+        global_config: &Config,
+        groups: &[String],
+        bucket: Option<&str>,
+    ) -> bool {
         let Some(bucket) = (match bucket {
             None => global_config.s3.buckets.first(),
             Some(str) => global_config.s3.buckets.iter().find(|x| x.alias.eq(str)),
@@ -315,6 +393,13 @@ impl SSOConfig {
             groups.iter().any(|x| x.eq(group))
         }
     }
+    /// Username of a valid token
+    pub fn username(&self, token: &str) -> Option<String> {
+        self.eject_username_groups(token)
+            .ok()
+            .map(|(username, _)| username)
+    }
+
     pub fn can_view(&self, config: &Config, token: &str, bucket: Option<&str>) -> bool {
         self.can_action(Action::View, config, token, bucket)
     }
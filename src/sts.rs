@@ -0,0 +1,232 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::time::Duration;
+
+use anyhow::bail;
+use s3::creds::{Credentials, Rfc3339OffsetDateTime};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::config::AssumeRoleConfig;
+use crate::sigv4;
+use crate::telemetry;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleResponse {
+    assume_role_result: AssumeRoleResult,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AssumeRoleResult {
+    credentials: RoleCredentials,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: Rfc3339OffsetDateTime,
+}
+
+/// Temporary credentials of `role.role_arn`, the request is signed with `base` credentials
+#[instrument(level = "trace", name = "sts_assume_role", skip_all, fields(role = %role.role_arn))]
+pub async fn assume_role(
+    role: &AssumeRoleConfig,
+    base: &Credentials,
+    timeout: u64,
+) -> anyhow::Result<Credentials> {
+    let duration = role.duration.to_string();
+    let mut params = vec![
+        ("Action", "AssumeRole"),
+        ("Version", "2011-06-15"),
+        ("RoleArn", role.role_arn.as_str()),
+        ("RoleSessionName", role.session_name.as_str()),
+        ("DurationSeconds", duration.as_str()),
+    ];
+    if let Some(external_id) = &role.external_id {
+        params.push(("ExternalId", external_id));
+    }
+    let cli = reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout))
+        .build()?;
+    let mut request = cli
+        .post(&role.endpoint)
+        .form(&params)
+        .headers(telemetry::injected_headers())
+        .build()?;
+
+    sigv4::sign(&mut request, base, &role.region, "sts")?;
+
+    let response = cli.execute(request).await?;
+    let status = response.status();
+    let text = response.text().await?;
+    if !status.is_success() {
+        bail!("STS responded {status}: {text}");
+    }
+    let response: AssumeRoleResponse = quick_xml::de::from_str(&text)?;
+    let credentials = response.assume_role_result.credentials;
+    Ok(Credentials {
+        access_key: Some(credentials.access_key_id),
+        secret_key: Some(credentials.secret_access_key),
+        security_token: None,
+        session_token: Some(credentials.session_token),
+        expiration: Some(credentials.expiration),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+
+    use super::*;
+
+    const RESPONSE: &str = r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <AssumedRoleUser>
+      <AssumedRoleId>AROA3XFRBF535PLBIFPI4:s3clix</AssumedRoleId>
+      <Arn>arn:aws:sts::123456789012:assumed-role/demo/s3clix</Arn>
+    </AssumedRoleUser>
+    <Credentials>
+      <AccessKeyId>ASIAJEXAMPLEXEG2JICEA</AccessKeyId>
+      <SecretAccessKey>9drTJvcXLB89EXAMPLELB8923FB892xMFI</SecretAccessKey>
+      <SessionToken>AQoXdzELDDY//////////wEaoAK1wvxJY12r2IrDFT2IvAzTCn3zHoZ7YNtpiQLF0MqZye/qwjzP2iEXAMPLE</SessionToken>
+      <Expiration>2030-07-15T23:28:33.359Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+  <ResponseMetadata>
+    <RequestId>c6104cbe-af31-11e0-8154-cbc7ccf896c7</RequestId>
+  </ResponseMetadata>
+</AssumeRoleResponse>"#;
+
+    const ERROR: &str = r#"<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code>
+<Message>not authorized to perform sts:AssumeRole</Message></Error></ErrorResponse>"#;
+
+    type Requests = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// Local STS answering `/` with RESPONSE and `/denied` with ERROR, requests are recorded
+    fn stub() -> (String, Requests) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (ok, denied) = (requests.clone(), requests.clone());
+        let router = Router::new()
+            .route(
+                "/",
+                post(|headers: HeaderMap, body: String| async move {
+                    ok.lock().unwrap().push((headers, body));
+                    RESPONSE
+                }),
+            )
+            .route(
+                "/denied",
+                post(|headers: HeaderMap, body: String| async move {
+                    denied.lock().unwrap().push((headers, body));
+                    (StatusCode::FORBIDDEN, ERROR)
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service());
+        tokio::spawn(server);
+        (format!("http://{address}"), requests)
+    }
+
+    fn role(endpoint: String) -> AssumeRoleConfig {
+        AssumeRoleConfig {
+            role_arn: "arn:aws:iam::123456789012:role/demo".to_owned(),
+            external_id: Some("partner-42".to_owned()),
+            session_name: "s3clix".to_owned(),
+            duration: 900,
+            endpoint,
+            region: "us-east-1".to_owned(),
+        }
+    }
+
+    fn base() -> Credentials {
+        Credentials {
+            access_key: Some("AKIDEXAMPLE".to_owned()),
+            secret_key: Some("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned()),
+            security_token: None,
+            session_token: None,
+            expiration: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_assume_role() {
+        let (endpoint, requests) = stub();
+        let credentials = assume_role(&role(endpoint), &base(), 5).await.unwrap();
+        assert_eq!(
+            credentials.access_key.as_deref(),
+            Some("ASIAJEXAMPLEXEG2JICEA")
+        );
+        assert!(credentials
+            .session_token
+            .unwrap()
+            .starts_with("AQoXdzELDDY"));
+        assert_eq!(
+            credentials.expiration.unwrap().0.unix_timestamp(),
+            1910388513
+        );
+
+        let (headers, body) = requests.lock().unwrap().remove(0);
+        // parsed as a query string, the body is encoded the same way
+        let form: Vec<(String, String)> = reqwest::Url::parse(&format!("http://sts/?{body}"))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        let field = |name: &str| {
+            form.iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+        assert_eq!(field("Action"), Some("AssumeRole"));
+        assert_eq!(field("Version"), Some("2011-06-15"));
+        assert_eq!(
+            field("RoleArn"),
+            Some("arn:aws:iam::123456789012:role/demo")
+        );
+        assert_eq!(field("RoleSessionName"), Some("s3clix"));
+        assert_eq!(field("DurationSeconds"), Some("900"));
+        assert_eq!(field("ExternalId"), Some("partner-42"));
+        let authorization = headers["authorization"].to_str().unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(authorization.contains("/us-east-1/sts/aws4_request"));
+        assert_eq!(headers["content-type"], "application/x-www-form-urlencoded");
+    }
+
+    #[tokio::test]
+    async fn test_assume_role_denied() {
+        let (endpoint, requests) = stub();
+        let err = assume_role(&role(format!("{endpoint}/denied")), &base(), 5)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("403"), "{err}");
+        assert!(err.contains("AccessDenied"), "{err}");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,341 @@
+/**
+Copyright 2025 Wargaming.Net
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+**/
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+use rand::RngCore;
+use s3::serde_types::Object;
+use serde::Serialize;
+use time::format_description::FormatItem;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+use crate::jobs::Job;
+use crate::s3::{DeleteReport, S3Client};
+
+/// Hidden folder of the deleted files: `.trash/<id>/<original path>`
+pub const TRASH_PREFIX: &str = ".trash/";
+/// Ids are deletion times with a random suffix, so they sort chronologically
+/// and deletes in the same millisecond get entries of their own
+const ID_FORMAT: &[FormatItem] =
+    format_description!("[year][month][day]T[hour][minute][second][subsecond digits:3]Z");
+/// Expired entries are looked for at most this often, seconds
+const PURGE_INTERVAL: u64 = 3600;
+
+/// Files removed by one delete
+#[derive(Serialize, Debug)]
+pub struct TrashEntry {
+    pub id: String,
+    /// when the files were moved to the trash
+    pub deleted: String,
+    /// original paths
+    pub paths: Vec<String>,
+    pub size: u64,
+}
+
+#[derive(Debug)]
+pub struct TrashConflict(pub String);
+
+impl Display for TrashConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} exists, it would be overwritten by restore", self.0)
+    }
+}
+
+impl std::error::Error for TrashConflict {}
+
+#[derive(Debug)]
+pub struct TrashNotFound(pub String);
+
+impl Display for TrashNotFound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Trash entry {} not found", self.0)
+    }
+}
+
+impl std::error::Error for TrashNotFound {}
+
+/// Object moved from `from` to `to`
+struct Move {
+    from: String,
+    to: String,
+    size: u64,
+}
+
+fn trash_id(time: OffsetDateTime) -> String {
+    let mut suffix = [0u8; 4];
+    rand::thread_rng().fill_bytes(&mut suffix);
+    format!("{}-{}", purge_cutoff(time), hex::encode(suffix))
+}
+
+/// Entries with ids below it were deleted before `time`: the time part of an id is fixed width
+fn purge_cutoff(time: OffsetDateTime) -> String {
+    time.format(ID_FORMAT).unwrap_or_default()
+}
+
+/// Groups the objects under `TRASH_PREFIX` into entries, oldest first
+fn group_entries(objects: Vec<Object>) -> Vec<TrashEntry> {
+    let mut entries: BTreeMap<String, TrashEntry> = BTreeMap::new();
+    for object in objects {
+        let Some((id, path)) = object
+            .key
+            .strip_prefix(TRASH_PREFIX)
+            .and_then(|x| x.split_once('/'))
+        else {
+            continue;
+        };
+        let entry = entries.entry(id.to_owned()).or_insert_with(|| TrashEntry {
+            id: id.to_owned(),
+            deleted: object.last_modified.clone(),
+            paths: Vec::new(),
+            size: 0,
+        });
+        entry.size += object.size;
+        if !path.ends_with(".placeholder") {
+            entry.paths.push(path.to_owned());
+        }
+    }
+    entries.into_values().collect()
+}
+
+/// Whether `path` is the trash or in it, it's reached through the trash calls only
+pub fn in_trash(path: &str) -> bool {
+    path.starts_with(TRASH_PREFIX) || path == TRASH_PREFIX.trim_end_matches('/')
+}
+
+impl S3Client {
+    /// Moves `path`, or everything under it if it's a folder, into a new trash entry
+    pub async fn trash(
+        &self,
+        path: &str,
+        folder: bool,
+        job: Option<&Job>,
+    ) -> anyhow::Result<DeleteReport> {
+        let objects: Vec<(String, u64)> = match folder {
+            true => self
+                .list_all(path)
+                .await?
+                .into_iter()
+                .filter(|x| !in_trash(&x.key))
+                .map(|x| (x.key, x.size))
+                .collect(),
+            false => vec![(path.to_owned(), self.size(path).await?)],
+        };
+        let id = trash_id(OffsetDateTime::now_utc());
+        info!("Moving {path} to trash entry {id}");
+        let moves = objects
+            .into_iter()
+            .map(|(key, size)| Move {
+                to: format!("{TRASH_PREFIX}{id}/{key}"),
+                from: key,
+                size,
+            })
+            .collect();
+        self.move_objects(moves, job).await
+    }
+
+    /// Trash entries, oldest first
+    pub async fn trash_entries(&self) -> anyhow::Result<Vec<TrashEntry>> {
+        Ok(group_entries(self.list_all(TRASH_PREFIX).await?))
+    }
+
+    /// Moves the files of the entry back, fails with `TrashConflict` if any of them exists.
+    /// Returns the restored paths
+    pub async fn restore_trash(&self, id: &str) -> anyhow::Result<Vec<String>> {
+        let moves = self.entry_objects(id).await?;
+        for Move { to, .. } in &moves {
+            if !to.ends_with(".placeholder") && self.exists(to).await? {
+                return Err(TrashConflict(to.clone()).into());
+            }
+        }
+        info!("Restoring trash entry {id}");
+        let paths = moves
+            .iter()
+            .map(|x| x.to.clone())
+            .filter(|x| !x.ends_with(".placeholder"))
+            .collect();
+        let report = self.move_objects(moves, None).await?;
+        if !report.failed.is_empty() {
+            warn!("Trash entry {id} is restored, but some of its files are left in trash");
+        }
+        Ok(paths)
+    }
+
+    /// Deletes the entry for good
+    pub async fn purge_trash(&self, id: &str) -> anyhow::Result<()> {
+        info!("Purging trash entry {id}");
+        let keys: Vec<String> = self
+            .entry_objects(id)
+            .await?
+            .into_iter()
+            .map(|x| x.from)
+            .collect();
+        let report = self.delete_objects(&keys, None).await?;
+        if !report.failed.is_empty() {
+            bail!(
+                "{} files of trash entry {id} are not deleted",
+                report.failed.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Moves of the entry objects back to their original paths
+    async fn entry_objects(&self, id: &str) -> anyhow::Result<Vec<Move>> {
+        if id.is_empty() || id.contains('/') {
+            return Err(TrashNotFound(id.to_owned()).into());
+        }
+        let prefix = format!("{TRASH_PREFIX}{id}/");
+        let objects: Vec<Move> = self
+            .list_all(&prefix)
+            .await?
+            .into_iter()
+            .map(|x| Move {
+                to: x.key[prefix.len()..].to_owned(),
+                from: x.key,
+                size: x.size,
+            })
+            .collect();
+        if objects.is_empty() {
+            return Err(TrashNotFound(id.to_owned()).into());
+        }
+        Ok(objects)
+    }
+
+    /// Copies every object first and deletes the sources after, so a failed copy
+    /// leaves the sources untouched: the copies made are removed again.
+    /// A cancelled job stops while copying only
+    async fn move_objects(
+        &self,
+        moves: Vec<Move>,
+        job: Option<&Job>,
+    ) -> anyhow::Result<DeleteReport> {
+        if let Some(job) = job {
+            job.set_total(moves.len() as u64);
+        }
+        for (i, Move { from, to, size }) in moves.iter().enumerate() {
+            let copied = match job.map(|x| x.check()).unwrap_or(Ok(())) {
+                Ok(_) => self
+                    .copy(from, None, *size, to)
+                    .await
+                    .map_err(|e| anyhow!("Cannot move {from} to {to}: {e}")),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = copied {
+                for Move { to: copied, .. } in &moves[..i] {
+                    if let Err(e) = self.delete(copied).await {
+                        warn!("Cannot remove {copied} after failed move: {e}");
+                    }
+                }
+                return Err(e);
+            }
+            if let Some(job) = job {
+                job.advance(1);
+            }
+        }
+        let sources: Vec<String> = moves.into_iter().map(|x| x.from).collect();
+        self.delete_objects(&sources, None).await
+    }
+
+    async fn purge_expired(&self, retention: u64) -> anyhow::Result<()> {
+        let cutoff = purge_cutoff(OffsetDateTime::now_utc() - Duration::from_secs(retention));
+        for entry in self.trash_entries().await? {
+            if entry.id < cutoff {
+                self.purge_trash(&entry.id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Purges entries older than the retention, the loop ends once the client is dropped
+    pub fn spawn_purge(self: &Arc<Self>) {
+        let client = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                let retention = match client.upgrade().and_then(|x| x.config.trash.clone()) {
+                    None => break,
+                    Some(x) => x.retention,
+                };
+                let sleep = retention.clamp(1, PURGE_INTERVAL);
+                tokio::time::sleep(Duration::from_secs(sleep)).await;
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                if let Err(e) = client.purge_expired(retention).await {
+                    warn!("Cannot purge trash of {}: {e}", client.config.alias);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::macros::datetime;
+
+    use super::*;
+
+    fn object(key: &str, size: u64) -> Object {
+        Object {
+            key: key.to_owned(),
+            size,
+            last_modified: "2025-01-01T00:00:00.000Z".to_owned(),
+            e_tag: None,
+            storage_class: None,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn test_group_entries() {
+        let entries = group_entries(vec![
+            object(".trash/20250101T000000000Z-00000001/a/.placeholder", 0),
+            object(".trash/20250101T000000000Z-00000001/a/b.txt", 2),
+            object(".trash/20250101T000000000Z-00000001/a/c.txt", 3),
+            object(".trash/20250101T000000000Z-00000002/a/b.txt", 4),
+            object(".trash/stray", 5),
+        ]);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, "20250101T000000000Z-00000001");
+        assert_eq!(entries[0].paths, vec!["a/b.txt", "a/c.txt"]);
+        assert_eq!(entries[0].size, 5);
+        assert_eq!(entries[1].paths, vec!["a/b.txt"]);
+    }
+
+    #[test]
+    fn test_purge_cutoff() {
+        let deleted = datetime!(2025-01-01 12:00:00.5 UTC);
+        let id = trash_id(deleted);
+        assert_ne!(id, trash_id(deleted));
+        assert!(id.as_str() >= purge_cutoff(deleted).as_str());
+        assert!(id < purge_cutoff(deleted + Duration::from_millis(1)));
+        // ids keep their order across digit carries
+        assert!(id < trash_id(datetime!(2025-01-01 12:00:10 UTC)));
+        assert!(trash_id(datetime!(2024-12-31 23:59:59.999 UTC)) < id);
+    }
+
+    #[test]
+    fn test_in_trash() {
+        assert!(in_trash(".trash"));
+        assert!(in_trash(".trash/20250101T000000000Z-00000001/a.txt"));
+        assert!(!in_trash(".trashcan/a.txt"));
+        assert!(!in_trash("a/.trash/b.txt"));
+    }
+}
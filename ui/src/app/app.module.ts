@@ -33,6 +33,8 @@ import {MatDialog, MatDialogModule, MatDialogRef} from "@angular/material/dialog
 import {MatInputModule} from "@angular/material/input";
 import {ReactiveFormsModule} from "@angular/forms";
 import {UploadFilesComponent} from './dialog/upload-files/upload-files.component';
+import {ShareFileComponent} from './dialog/share-file/share-file.component';
+import {FileRequestComponent} from './dialog/file-request/file-request.component';
 import {ConfirmDialogComponent} from './dialog/confirm-dialog/confirm-dialog.component';
 import {ThemeSwitcherComponent} from "./shared/theme-switcher/theme-switcher.component";
 import {LayoutModule} from "./shared/layout/layout.module";
@@ -44,6 +46,8 @@ import {LayoutModule} from "./shared/layout/layout.module";
         BreadcrumbsComponent,
         CreateFolderComponent,
         UploadFilesComponent,
+        ShareFileComponent,
+        FileRequestComponent,
         ConfirmDialogComponent
     ],
     bootstrap: [AppComponent],
@@ -0,0 +1,67 @@
+/**
+ Copyright 2025 Wargaming.Net
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+ http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+**/
+
+import {Component, Inject} from '@angular/core';
+import {MAT_DIALOG_DATA, MatDialogRef} from "@angular/material/dialog";
+import {FormControl, Validators} from "@angular/forms";
+import {Clipboard} from "@angular/cdk/clipboard";
+import {HttpErrorResponse} from "@angular/common/http";
+import {DataServiceService} from "../../services/data-service.service";
+import {NotificationService} from "../../services/notification.service";
+import {IFile} from "../../models/files.model";
+
+const MB = 1024 * 1024;
+
+
+@Component({
+    selector: 'app-file-request',
+    templateUrl: './file-request.component.html',
+    styleUrls: ['./file-request.component.scss'],
+    standalone: false
+})
+export class FileRequestComponent {
+    expiresHoursFC = new FormControl(24, [Validators.required, Validators.min(1)]);
+    maxFileSizeFC = new FormControl<number | null>(null, [Validators.min(1)]);
+    maxTotalSizeFC = new FormControl<number | null>(null, [Validators.min(1)]);
+
+    constructor(public dialogRef: MatDialogRef<FileRequestComponent>,
+                @Inject(MAT_DIALOG_DATA) public data: IFile,
+                private dataService: DataServiceService,
+                private notificationService: NotificationService,
+                private clipboard: Clipboard) {
+    }
+
+    invalid() {
+        return this.expiresHoursFC.invalid || this.maxFileSizeFC.invalid || this.maxTotalSizeFC.invalid;
+    }
+
+    createFileRequest() {
+        this.dataService.createFileRequest(this.data.path, {
+            expires_in: (this.expiresHoursFC.value ?? 24) * 3600,
+            max_file_size: this.maxFileSizeFC.value ? this.maxFileSizeFC.value * MB : null,
+            max_total_size: this.maxTotalSizeFC.value ? this.maxTotalSizeFC.value * MB : null
+        }).subscribe({
+            next: (request) => {
+                this.clipboard.copy(`${window.location.origin}/r/${request.token}`);
+                this.notificationService.showNotification('Upload link has been copied into clipboard');
+                this.dialogRef.close(false);
+            },
+            error: (err: HttpErrorResponse) => {
+                this.notificationService.showNotification(`Upload link can not be created, due to: ${err.error || err.message}`);
+            }
+        });
+    }
+}
@@ -0,0 +1,65 @@
+/**
+ Copyright 2025 Wargaming.Net
+
+ Licensed under the Apache License, Version 2.0 (the "License");
+ you may not use this file except in compliance with the License.
+ You may obtain a copy of the License at
+
+ http://www.apache.org/licenses/LICENSE-2.0
+
+ Unless required by applicable law or agreed to in writing, software
+ distributed under the License is distributed on an "AS IS" BASIS,
+ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ See the License for the specific language governing permissions and
+ limitations under the License.
+**/
+
+import {Component, Inject} from '@angular/core';
+import {MAT_DIALOG_DATA, MatDialogRef} from "@angular/material/dialog";
+import {FormControl, Validators} from "@angular/forms";
+import {Clipboard} from "@angular/cdk/clipboard";
+import {HttpErrorResponse} from "@angular/common/http";
+import {DataServiceService} from "../../services/data-service.service";
+import {NotificationService} from "../../services/notification.service";
+import {IFile} from "../../models/files.model";
+
+
+@Component({
+    selector: 'app-share-file',
+    templateUrl: './share-file.component.html',
+    styleUrls: ['./share-file.component.scss'],
+    standalone: false
+})
+export class ShareFileComponent {
+    expiresHoursFC = new FormControl(24, [Validators.required, Validators.min(1)]);
+    passwordFC = new FormControl('');
+    maxDownloadsFC = new FormControl<number | null>(null, [Validators.min(1)]);
+
+    constructor(public dialogRef: MatDialogRef<ShareFileComponent>,
+                @Inject(MAT_DIALOG_DATA) public data: IFile,
+                private dataService: DataServiceService,
+                private notificationService: NotificationService,
+                private clipboard: Clipboard) {
+    }
+
+    invalid() {
+        return this.expiresHoursFC.invalid || this.maxDownloadsFC.invalid;
+    }
+
+    createShare() {
+        this.dataService.createShare(this.data.path, {
+            expires_in: (this.expiresHoursFC.value ?? 24) * 3600,
+            password: this.passwordFC.value || null,
+            max_downloads: this.maxDownloadsFC.value || null
+        }).subscribe({
+            next: (share) => {
+                this.clipboard.copy(`${window.location.origin}/s/${share.token}`);
+                this.notificationService.showNotification('Public link has been copied into clipboard');
+                this.dialogRef.close(false);
+            },
+            error: (err: HttpErrorResponse) => {
+                this.notificationService.showNotification(`File ${this.data.name} can not be shared, due to: ${err.error || err.message}`);
+            }
+        });
+    }
+}
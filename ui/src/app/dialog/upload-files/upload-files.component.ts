@@ -21,6 +21,7 @@ import {HttpErrorResponse, HttpEvent, HttpEventType} from "@angular/common/http"
 import {ConfirmDialogComponent} from "../confirm-dialog/confirm-dialog.component";
 import {Subscription} from "rxjs";
 import {NotificationService} from "../../services/notification.service";
+import {IDirectUpload, IUploadedPart} from "../../models/files.model";
 
 @Component({
     selector: 'app-upload-files',
@@ -76,25 +77,70 @@ export class UploadFilesComponent implements OnDestroy {
 
             this.uploadStatus[file.name] = {uploading: true, success: false, failed: false, progress: 0};
 
-            const subscription = this.dataService.uploadFile(fileURL, file).subscribe({
-                next: (event: HttpEvent<any>) => {
-                    if (event.type === HttpEventType.UploadProgress) {
-                        this.uploadStatus[file.name].progress = Math.round((100 * event.loaded) / event.total!) - this.overtimeCoefficient;
-                    } else if (event.type === HttpEventType.Response) {
-                        this.uploadStatus[file.name] = {uploading: false, success: true, failed: false, progress: 0};
-                        this.notificationService.showNotification(`${file.name} was successfully uploaded!`);
-                        this.uploadFileByFile(files);
-                    }
-
-                },
-                error: (err: HttpErrorResponse) => {
-                    this.uploadStatus[file.name] = {uploading: false, success: false, failed: true, progress: 0};
-                    this.notificationService.showNotification(`${file.name} can not be uploaded, due to: ${err.message}`);
-                    this.uploadFileByFile(files);
+            // direct upload to S3 if the bucket allows it, through the server otherwise
+            const subscription = this.dataService.startDirectUpload(fileURL, file.size).subscribe({
+                next: (upload: IDirectUpload) => this.uploadParts(file, fileURL, upload, [], files),
+                error: (err: HttpErrorResponse) => err.status === 501
+                    ? this.uploadThroughServer(file, fileURL, files)
+                    : this.uploadFailed(file, err.message, files)
+            });
+            this.allSubscriptions.add(subscription);
+        }
+    }
+
+    uploadThroughServer(file: File, fileURL: string, files: File[]) {
+        const subscription = this.dataService.uploadFile(fileURL, file).subscribe({
+            next: (event: HttpEvent<any>) => {
+                if (event.type === HttpEventType.UploadProgress) {
+                    this.uploadStatus[file.name].progress = Math.round((100 * event.loaded) / event.total!) - this.overtimeCoefficient;
+                } else if (event.type === HttpEventType.Response) {
+                    this.uploadSucceeded(file, files);
                 }
+
+            },
+            error: (err: HttpErrorResponse) => this.uploadFailed(file, err.message, files)
+        });
+        this.allSubscriptions.add(subscription);
+    }
+
+    uploadParts(file: File, fileURL: string, upload: IDirectUpload, done: IUploadedPart[], files: File[]) {
+        if (done.length === upload.parts.length) {
+            const subscription = this.dataService.completeDirectUpload(upload.path, upload.upload_id, done).subscribe({
+                next: () => this.uploadSucceeded(file, files),
+                error: (err: HttpErrorResponse) => this.uploadFailed(file, err.message, files)
             });
             this.allSubscriptions.add(subscription);
+            return;
         }
+        const part = upload.parts[done.length];
+        const start = (part.part_number - 1) * upload.part_size;
+        const subscription = this.dataService.uploadPart(part.url, file.slice(start, start + upload.part_size)).subscribe({
+            next: (event: HttpEvent<any>) => {
+                if (event.type === HttpEventType.UploadProgress) {
+                    this.uploadStatus[file.name].progress = Math.round((100 * (start + event.loaded)) / file.size) - this.overtimeCoefficient;
+                } else if (event.type === HttpEventType.Response) {
+                    const etag = event.headers.get('ETag') ?? '';
+                    this.uploadParts(file, fileURL, upload, [...done, {part_number: part.part_number, etag}], files);
+                }
+            },
+            error: (err: HttpErrorResponse) => {
+                this.dataService.abortDirectUpload(upload.path, upload.upload_id).subscribe({error: () => {}});
+                this.uploadFailed(file, err.message, files);
+            }
+        });
+        this.allSubscriptions.add(subscription);
+    }
+
+    uploadSucceeded(file: File, files: File[]) {
+        this.uploadStatus[file.name] = {uploading: false, success: true, failed: false, progress: 0};
+        this.notificationService.showNotification(`${file.name} was successfully uploaded!`);
+        this.uploadFileByFile(files);
+    }
+
+    uploadFailed(file: File, message: string, files: File[]) {
+        this.uploadStatus[file.name] = {uploading: false, success: false, failed: true, progress: 0};
+        this.notificationService.showNotification(`${file.name} can not be uploaded, due to: ${message}`);
+        this.uploadFileByFile(files);
     }
 
     initFilesUploadStatus() {
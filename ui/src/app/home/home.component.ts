@@ -23,6 +23,8 @@ import {DialogComponent} from "../dialog/dialog.component";
 import {comparator} from '../shared/utility/utility';
 import {CreateFolderComponent} from "../dialog/create-folder/create-folder.component";
 import {UploadFilesComponent} from "../dialog/upload-files/upload-files.component";
+import {ShareFileComponent} from "../dialog/share-file/share-file.component";
+import {FileRequestComponent} from "../dialog/file-request/file-request.component";
 import {
     ConfirmationDialodData,
     ConfirmDialogComponent,
@@ -35,9 +37,12 @@ import {HttpErrorResponse} from "@angular/common/http";
 import {rxResource, toSignal} from "@angular/core/rxjs-interop";
 import {Clipboard} from "@angular/cdk/clipboard";
 import {getCookie} from '../shared/utility/cookie';
+import {BytesToSizePipe} from '../shared/bytes-to-size.pipe';
 import {DataServiceService} from "../services/data-service.service";
-import {catchError, finalize, firstValueFrom, forkJoin, map, of} from "rxjs";
+import {catchError, finalize, firstValueFrom, forkJoin, last, map, of, switchMap, takeWhile, timer} from "rxjs";
 
+/** How often a running background job is polled, ms */
+const JOB_POLL_INTERVAL = 1000;
 
 @Component({
     selector: 'app-home',
@@ -291,6 +296,14 @@ export class HomeComponent {
             case FileAction.deleteFolder:
                 this.deleteFolder(event.item);
                 break;
+
+            case FileAction.share:
+                this.shareFile(event.item);
+                break;
+
+            case FileAction.fileRequest:
+                this.createFileRequest(event.item);
+                break;
         }
     }
 
@@ -326,6 +339,29 @@ export class HomeComponent {
         }
     }
 
+    shareFile(file: IFile) {
+        this.dialog.open(ShareFileComponent, {
+            panelClass: 'action-dialog',
+            position: {top: '10vh'},
+            minWidth: '20vw',
+            maxWidth: '50vw',
+            data: file
+        });
+    }
+
+    createFileRequest(folder: IFile) {
+        if (!this.canUserUploadFiles()) {
+            return;
+        }
+        this.dialog.open(FileRequestComponent, {
+            panelClass: 'action-dialog',
+            position: {top: '10vh'},
+            minWidth: '20vw',
+            maxWidth: '50vw',
+            data: folder
+        });
+    }
+
     downloadFile(file: IFile) {
         const encodedFilePath = encodeURIComponent(file.path);
         window.open(`/api/download/${encodedFilePath}`, '_blank');
@@ -353,18 +389,31 @@ export class HomeComponent {
             return;
         }
 
+        const preview = await firstValueFrom(this.dataService.deleteFolderPreview(folder.path).pipe(
+            catchError(() => of(null))));
+        const dialogText = preview
+            ? `${preview.count} files (${new BytesToSizePipe().transform(preview.size)}) in this folder will be deleted.`
+            : 'This action will result in the complete deletion of all contents in this folder.';
         const confirmed = await this.showConfirmationDialog({
-            dialogText: 'This action will result in the complete deletion of all contents in this folder.',
+            dialogText,
             dialogType: DialogType.WARNING
         });
 
         if (!confirmed) return;
 
         this.dataService.deleteFolder(folder.path).pipe(
+            switchMap(job => timer(0, JOB_POLL_INTERVAL).pipe(
+                switchMap(() => this.dataService.getJob(job.id)),
+                takeWhile(job => job.status === 'running', true),
+                last())),
             finalize(() => this.filesUpdaterTrigger.update(v => v + 1)))
             .subscribe({
-                next: () => {
-                    this.notificationService.showNotification(`The folder ${folder.name} was successfully deleted!`);
+                next: (job) => {
+                    if (job.status === 'completed') {
+                        this.notificationService.showNotification(`The folder ${folder.name} was successfully deleted!`);
+                    } else {
+                        this.notificationService.showNotification(`The folder ${folder.name} can not be deleted, due to: ${job.error}`);
+                    }
                 },
                 error: (err: HttpErrorResponse) => {
                     this.notificationService.showNotification(`The folder ${folder.name} can not be deleted, due to: ${err.message}`);
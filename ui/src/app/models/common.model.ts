@@ -20,6 +20,8 @@ export enum FileAction {
     copyLink = 2,
     copyCdnLink = 3,
     deleteFolder = 4,
+    share = 5,
+    fileRequest = 6,
 }
 
 export interface IFileListQueryParams {
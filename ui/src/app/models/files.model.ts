@@ -22,6 +22,79 @@ export interface IFile {
     cdn_url?: string,
 }
 
+export interface IDirectPart {
+    part_number: number,
+    url: string,
+}
+
+export interface IDirectUpload {
+    path: string, // differs from the requested one if the bucket renames existing files
+    upload_id: string,
+    part_size: number,
+    parts: IDirectPart[],
+}
+
+export interface IDeletePreview {
+    count: number,
+    size: number,
+    sample: string[],
+}
+
+export interface IJobReport {
+    id: string,
+    kind: string,
+    bucket: string,
+    path: string,
+    status: 'running' | 'completed' | 'failed' | 'cancelled',
+    created: number,
+    finished?: number,
+    done: number,
+    total: number,
+    error?: string,
+}
+
+export interface IShareRequest {
+    expires_in: number,
+    password: string | null,
+    max_downloads: number | null,
+}
+
+export interface IShareInfo {
+    token: string,
+    bucket: string,
+    path: string,
+    user: string,
+    created: number,
+    expires: number,
+    password: boolean,
+    max_downloads?: number,
+    downloads: number,
+}
+
+export interface IFileRequestCreate {
+    expires_in: number,
+    max_file_size: number | null,
+    max_total_size: number | null,
+}
+
+export interface IFileRequestInfo {
+    token: string,
+    bucket: string,
+    prefix: string,
+    user: string,
+    created: number,
+    expires: number,
+    max_file_size?: number,
+    max_total_size: number,
+    uploaded: number,
+    files: number,
+}
+
+export interface IUploadedPart {
+    part_number: number,
+    etag: string,
+}
+
 export enum PresentationStyle {
     list = 1,
     grid = 0,
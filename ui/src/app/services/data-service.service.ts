@@ -19,7 +19,7 @@ import {HttpClient, HttpHeaders, HttpParams, HttpRequest} from "@angular/common/
 import * as path from "path-browserify";
 import {environment} from "../../environments/environment";
 import {map} from "rxjs";
-import {IFile} from "../models/files.model";
+import {IDeletePreview, IDirectUpload, IFile, IJobReport, IFileRequestCreate, IFileRequestInfo, IShareInfo, IShareRequest, IUploadedPart} from "../models/files.model";
 
 @Injectable({
     providedIn: 'root'
@@ -75,6 +75,30 @@ export class DataServiceService {
         return this.http.request(req);
     }
 
+    startDirectUpload(filePath: string, size: number) {
+        const encodedFilePath = encodeURIComponent(filePath);
+        const URL = path.join(environment.apiPrefix, environment.endpoints.directUploadEndpoint, encodedFilePath);
+        return this.http.post<IDirectUpload>(URL, {size});
+    }
+
+    // goes straight to S3, the bucket CORS policy has to allow PUT and expose ETag
+    uploadPart(url: string, chunk: Blob) {
+        const req = new HttpRequest('PUT', url, chunk, {reportProgress: true, responseType: 'text'});
+        return this.http.request(req);
+    }
+
+    completeDirectUpload(filePath: string, uploadId: string, parts: IUploadedPart[]) {
+        const encodedFilePath = encodeURIComponent(filePath);
+        const URL = path.join(environment.apiPrefix, environment.endpoints.directCompleteEndpoint, encodedFilePath);
+        return this.http.post(URL, {upload_id: uploadId, parts}, {responseType: 'text'});
+    }
+
+    abortDirectUpload(filePath: string, uploadId: string) {
+        const encodedFilePath = encodeURIComponent(filePath);
+        const URL = path.join(environment.apiPrefix, environment.endpoints.directAbortEndpoint, encodedFilePath);
+        return this.http.post(URL, {upload_id: uploadId}, {responseType: 'text'});
+    }
+
     getBuckets() {
         const URL = path.join(environment.apiPrefix, environment.endpoints.getBucketsEndpoint);
         return this.http.get<string[]>(URL);
@@ -95,7 +119,30 @@ export class DataServiceService {
     deleteFolder(folderPath: string) {
         const encodedFolderPath = encodeURIComponent(folderPath);
         const URL = path.join(environment.apiPrefix, environment.endpoints.deleteFolderEndpoint, encodedFolderPath);
-        return this.http.delete(URL, {responseType: 'text'});
+        return this.http.delete<IJobReport>(URL);
+    }
+
+    getJob(id: string) {
+        const URL = path.join(environment.apiPrefix, environment.endpoints.jobsEndpoint, id);
+        return this.http.get<IJobReport>(URL);
+    }
+
+    deleteFolderPreview(folderPath: string) {
+        const encodedFolderPath = encodeURIComponent(folderPath);
+        const URL = path.join(environment.apiPrefix, environment.endpoints.deleteFolderEndpoint, encodedFolderPath);
+        return this.http.delete<IDeletePreview>(URL, {params: {dry_run: true}});
+    }
+
+    createShare(filePath: string, request: IShareRequest) {
+        const encodedPath = encodeURIComponent(filePath);
+        const URL = path.join(environment.apiPrefix, environment.endpoints.shareEndpoint, encodedPath);
+        return this.http.post<IShareInfo>(URL, request);
+    }
+
+    createFileRequest(folderPath: string, request: IFileRequestCreate) {
+        const encodedPath = encodeURIComponent(folderPath);
+        const URL = path.join(environment.apiPrefix, environment.endpoints.fileRequestEndpoint, encodedPath);
+        return this.http.post<IFileRequestInfo>(URL, request);
     }
 
     getTextFile(filePath: string) {
@@ -34,6 +34,9 @@ export class ItemComponent {
     @Input()
     disableFileDelete: boolean = true;
 
+    @Input()
+    disableFileUpload: boolean = true;
+
     @Output() invokeFileAction: EventEmitter<{ item: IFile, action: FileAction }> = new EventEmitter();
 
     protected readonly fileAction = FileAction;
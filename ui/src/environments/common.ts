@@ -30,7 +30,13 @@ export const settings = {
     searchFilesEndpoint: 'search',
     getBucketsEndpoint: 'buckets',
     selectBucketEndpoint: 'bucket',
-    deleteFolderEndpoint: 'deleteFolder'
+    deleteFolderEndpoint: 'deleteFolder',
+    directUploadEndpoint: 'directUpload',
+    directCompleteEndpoint: 'directComplete',
+    directAbortEndpoint: 'directAbort',
+    jobsEndpoint: 'jobs',
+    shareEndpoint: 'share',
+    fileRequestEndpoint: 'fileRequest'
   },
   viewerExtensions: {
     image: ['png', 'jpeg'],